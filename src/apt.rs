@@ -0,0 +1,173 @@
+//! Generation of the indexes of an APT repository:
+//!
+//! ```text
+//! dists/<distribution>/Release
+//! dists/<distribution>/<component>/binary-<architecture>/Packages
+//! dists/<distribution>/<component>/binary-<architecture>/Packages.gz
+//! ```
+//!
+//! Paths of packages are relative to the directory containing `dists`.
+
+use checksum;
+use chrono::Utc;
+use deb::Control;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A package to list in the indexes.
+pub struct AptPackage {
+    /// Path of the package in the repository, i.e. its `Filename` field.
+    pub path: PathBuf,
+    /// Path of the local copy of the package.
+    pub filename: PathBuf,
+    pub control: Control,
+}
+
+pub struct AptRepository<'a> {
+    pub origin: &'a str,
+    pub label: &'a str,
+    pub distributions: &'a [String],
+    pub components: &'a [String],
+    /// Without architectures, those of the packages are used.
+    pub architectures: &'a [String],
+}
+
+impl<'a> AptRepository<'a> {
+    /// Writes the indexes listing `packages` below `dir` and returns
+    /// their path, relative to `dir`. Every package is listed in every
+    /// distribution and component; `Architecture: all` packages are
+    /// listed for every architecture.
+    pub fn write_indexes(&self, packages: &[AptPackage], dir: &Path)
+        -> io::Result<Vec<PathBuf>>
+    {
+        let architectures = self.architectures(packages);
+
+        let mut entries = vec![];
+        for package in packages {
+            entries.push((package.control.package().unwrap_or(""),
+                          package.control.version().unwrap_or(""),
+                          package.control.architecture().unwrap_or(""),
+                          index_entry(package)?));
+        }
+        entries.sort();
+
+        let mut written = vec![];
+        for distribution in self.distributions {
+            let dist_dir = PathBuf::from("dists").join(distribution);
+            let mut indexes = vec![];
+            for component in self.components {
+                for architecture in architectures.iter() {
+                    let content = entries.iter()
+                        .filter(|&&(_, _, arch, _)| {
+                            arch == architecture || arch == "all"
+                        })
+                        .map(|&(_, _, _, ref entry)| entry.as_str())
+                        .collect::<Vec<&str>>()
+                        .join("\n");
+
+                    let index = PathBuf::from(component)
+                        .join(format!("binary-{}", architecture))
+                        .join("Packages");
+                    let path = dir.join(&dist_dir).join(&index);
+                    fs::create_dir_all(path.parent().unwrap())?;
+                    File::create(&path)?.write_all(content.as_bytes())?;
+
+                    let gz_index = index.with_extension("gz");
+                    let gz_path = dir.join(&dist_dir).join(&gz_index);
+                    let mut encoder = GzEncoder::new(File::create(&gz_path)?,
+                                                     Compression::Best);
+                    encoder.write_all(content.as_bytes())?;
+                    encoder.finish()?;
+
+                    indexes.push(index);
+                    indexes.push(gz_index);
+                }
+            }
+
+            let release = self.release(distribution,
+                                       &architectures,
+                                       &dir.join(&dist_dir),
+                                       &indexes)?;
+            File::create(dir.join(&dist_dir).join("Release"))?
+                .write_all(release.as_bytes())?;
+
+            written.extend(indexes.iter().map(|i| dist_dir.join(i)));
+            written.push(dist_dir.join("Release"));
+        }
+        Ok(written)
+    }
+
+    fn architectures(&self, packages: &[AptPackage]) -> Vec<String> {
+        if !self.architectures.is_empty() {
+            return self.architectures.to_vec();
+        }
+        let mut architectures = packages.iter()
+            .filter_map(|p| p.control.architecture())
+            .filter(|a| *a != "all")
+            .map(String::from)
+            .collect::<Vec<String>>();
+        architectures.sort();
+        architectures.dedup();
+        if architectures.is_empty() {
+            architectures.push(String::from("all"));
+        }
+        architectures
+    }
+
+    /// Returns the `Release` file of a distribution, with the checksums
+    /// of its indexes.
+    fn release(&self,
+               distribution: &str,
+               architectures: &[String],
+               dist_dir: &Path,
+               indexes: &[PathBuf])
+        -> io::Result<String>
+    {
+        let mut sha1 = String::from("SHA1:\n");
+        let mut sha256 = String::from("SHA256:\n");
+        for index in indexes {
+            let path = dist_dir.join(index);
+            let size = fs::metadata(&path)?.len();
+            sha1.push_str(&format!(" {} {:>8} {}\n",
+                                   checksum::sha1_file(&path)?,
+                                   size,
+                                   index.display()));
+            sha256.push_str(&format!(" {} {:>8} {}\n",
+                                     checksum::sha256_file(&path)?,
+                                     size,
+                                     index.display()));
+        }
+
+        Ok(format!("Origin: {}\n\
+                    Label: {}\n\
+                    Suite: {}\n\
+                    Codename: {}\n\
+                    Date: {}\n\
+                    Architectures: {}\n\
+                    Components: {}\n\
+                    {}{}",
+                   self.origin,
+                   self.label,
+                   distribution,
+                   distribution,
+                   Utc::now().format("%a, %d %b %Y %H:%M:%S UTC"),
+                   architectures.join(" "),
+                   self.components.join(" "),
+                   sha1,
+                   sha256))
+    }
+}
+
+/// Returns the control paragraph of a package followed by its location
+/// and checksums, as listed in `Packages`.
+fn index_entry(package: &AptPackage) -> io::Result<String> {
+    Ok(format!("{}\nFilename: {}\nSize: {}\nSHA1: {}\nSHA256: {}\n",
+               package.control.paragraph(),
+               package.path.display(),
+               fs::metadata(&package.filename)?.len(),
+               checksum::sha1_file(&package.filename)?,
+               checksum::sha256_file(&package.filename)?))
+}
@@ -0,0 +1,668 @@
+use backend::{Backend, FileRecord, PackageProps, RecordChange, UploadOptions,
+              VersionMetadata, VersionRecord, check_version, http,
+              relative_path, versions_since};
+use bintray::client::BintrayError;
+use checksum;
+use hyper::client::{Body, Client, Response};
+use hyper::header::{Authorization, Basic, Bearer, Headers};
+use hyper::method::Method;
+use hyper::Url;
+use serde_json;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Path of the JSON file holding the version properties, relative to the
+/// version folder.
+const METADATA_PATH: &'static str = ".version.json";
+
+/// Folder of the empty files marking unpublished files, relative to the
+/// version folder.
+const UNPUBLISHED_PATH: &'static str = ".unpublished/";
+
+/// A package stored in a generic JFrog Artifactory repository:
+///
+/// ```text
+/// <repository>/<package>/<version>/<files...>
+/// <repository>/<package>/<version>/.version.json
+/// <repository>/<package>/<version>/.unpublished/<files...>
+/// ```
+///
+/// `subject` is the base URL of the Artifactory instance and `repository`
+/// the repository key. As with S3, unpublished files are tracked with
+/// empty marker files so concurrent uploads don't have to update the
+/// same file. Folders and checksums are listed with the storage API.
+///
+/// Listing versions costs one request for the package folder plus one
+/// per version, to read its `.version.json`: the storage API can't return
+/// the content of files.
+pub struct ArtifactoryBackend {
+    client: Client,
+    base_url: Url,
+    credentials: Credentials,
+    repository: String,
+    package: String,
+}
+
+pub enum Credentials {
+    Anonymous,
+    /// A username with its password or API key.
+    Basic(String, String),
+    /// An access token.
+    Token(String),
+}
+
+enum Payload<'a> {
+    Empty,
+    Bytes(&'a [u8]),
+    File(&'a mut File, u64),
+}
+
+#[derive(Debug, Deserialize)]
+struct FolderInfo {
+    #[serde(default)]
+    children: Vec<FolderChild>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FolderChild {
+    uri: String,
+    folder: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileList {
+    files: Vec<ListedFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListedFile {
+    uri: String,
+    size: u64,
+    folder: bool,
+    sha1: Option<String>,
+    sha2: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    errors: Vec<ErrorDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    message: Option<String>,
+}
+
+impl ArtifactoryBackend {
+    /// `base_url` is the URL of the Artifactory instance, e.g.
+    /// `https://example.jfrog.io/artifactory`.
+    pub fn new(base_url: &str,
+               credentials: Credentials,
+               repository: &str,
+               package: &str)
+        -> Result<ArtifactoryBackend, BintrayError>
+    {
+        let base_url = Url::parse(base_url)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput,
+                                        format!("{}: {}", base_url, e)))?;
+
+        Ok(ArtifactoryBackend {
+            client: http::new_client()?,
+            base_url: base_url,
+            credentials: credentials,
+            repository: String::from(repository),
+            package: String::from(package),
+        })
+    }
+
+    /// Fails if the version would point outside of the package folder.
+    fn version_path(&self, version: &str) -> Result<String, BintrayError> {
+        check_version(version)?;
+        Ok(format!("{}/{}/", self.package, version))
+    }
+
+    fn file_path(&self, version: &str, path: &Path)
+        -> Result<String, BintrayError>
+    {
+        Ok(format!("{}{}",
+                   self.version_path(version)?,
+                   relative_path(path).to_string_lossy()))
+    }
+
+    fn marker_path(&self, version: &str, path: &Path)
+        -> Result<String, BintrayError>
+    {
+        Ok(format!("{}{}{}",
+                   self.version_path(version)?,
+                   UNPUBLISHED_PATH,
+                   relative_path(path).to_string_lossy()))
+    }
+
+    /// Returns the URL of `path` in the repository, below `api` (e.g.
+    /// `api/storage`) if set.
+    fn url(&self, api: Option<&str>, path: &str) -> Url {
+        let mut url = self.base_url.clone();
+        {
+            let mut segments = url.path_segments_mut()
+                .expect("base URL can't be a base");
+            segments.pop_if_empty();
+            match api {
+                Some(api) => { segments.extend(api.split('/')); }
+                None      => { }
+            }
+            segments.push(&self.repository);
+            segments.extend(path.split('/').filter(|s| !s.is_empty()));
+        }
+        url
+    }
+
+    /// Sends a request and returns the response if its status is a
+    /// success.
+    fn request(&self,
+               method: Method,
+               url: Url,
+               extra_headers: &[(&str, &str)],
+               payload: Payload)
+        -> Result<Response, BintrayError>
+    {
+        let mut headers = Headers::new();
+        match self.credentials {
+            Credentials::Anonymous => { }
+            Credentials::Basic(ref username, ref password) => {
+                headers.set(Authorization(Basic {
+                    username: username.clone(),
+                    password: Some(password.clone()),
+                }));
+            }
+            Credentials::Token(ref token) => {
+                headers.set(Authorization(Bearer { token: token.clone() }));
+            }
+        }
+        for &(name, value) in extra_headers {
+            headers.set_raw(String::from(name),
+                            vec![value.as_bytes().to_vec()]);
+        }
+
+        let what = format!("{} {}", method, url);
+        let builder = self.client.request(method, url).headers(headers);
+        let response = match payload {
+            Payload::Empty => builder.body(Body::BufBody(&[], 0)).send(),
+            Payload::Bytes(bytes) => {
+                builder.body(Body::BufBody(bytes, bytes.len())).send()
+            }
+            Payload::File(file, len) => {
+                builder.body(Body::SizedBody(file, len)).send()
+            }
+        };
+        let response = response.map_err(|e| http::request_error(e, &what))?;
+        check_status(response, &what)
+    }
+
+    fn get(&self, url: Url) -> Result<Response, BintrayError> {
+        self.request(Method::Get, url, &[], Payload::Empty)
+    }
+
+    /// Returns `false` if `path` doesn't exist.
+    fn exists(&self, path: &str) -> Result<bool, BintrayError> {
+        match self.get(self.url(Some("api/storage"), path)) {
+            Ok(_) => Ok(true),
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Lists the files of a version, including its metadata and markers.
+    fn list_version(&self, version: &str)
+        -> Result<Vec<ListedFile>, BintrayError>
+    {
+        self.list_folder(&self.version_path(version)?)
+            .map_err(|e| match e {
+                BintrayError::Io(ref e)
+                    if e.kind() == io::ErrorKind::NotFound => not_found(version),
+                e => e,
+            })
+    }
+
+    /// Lists the files below the `path` folder, with their path relative
+    /// to it.
+    fn list_folder(&self, path: &str) -> Result<Vec<ListedFile>, BintrayError> {
+        let mut url = self.url(Some("api/storage"), path);
+        url.set_query(Some("list&deep=1&listFolders=0"));
+        let list: FileList = serde_json::from_reader(self.get(url)?)
+            .map_err(BintrayError::Json)?;
+        Ok(list.files.into_iter().filter(|f| !f.folder).collect())
+    }
+
+    fn delete(&self, path: &str) -> Result<(), BintrayError> {
+        match self.request(Method::Delete, self.url(None, path), &[],
+                           Payload::Empty) {
+            Ok(_) => Ok(()),
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns `None` if the version has no metadata file.
+    fn get_metadata(&self, version: &str)
+        -> Result<Option<VersionMetadata>, BintrayError>
+    {
+        let path = format!("{}{}", self.version_path(version)?, METADATA_PATH);
+        match self.get(self.url(None, &path)) {
+            Ok(response) => {
+                let metadata = serde_json::from_reader(response)
+                    .map_err(BintrayError::Json)?;
+                Ok(Some(metadata))
+            }
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns `None` if the version doesn't exist. A version folder
+    /// without a metadata file (e.g. populated by another tool) is a
+    /// version without properties.
+    fn read_metadata(&self, version: &str)
+        -> Result<Option<VersionMetadata>, BintrayError>
+    {
+        match self.get_metadata(version)? {
+            Some(metadata) => Ok(Some(metadata)),
+            None if self.exists(&self.version_path(version)?)? => {
+                Ok(Some(VersionMetadata::default()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write_metadata(&self, version: &str, metadata: &VersionMetadata)
+        -> Result<(), BintrayError>
+    {
+        let path = format!("{}{}", self.version_path(version)?, METADATA_PATH);
+        let json = serde_json::to_string_pretty(metadata)
+            .map_err(BintrayError::Json)?;
+        self.request(Method::Put,
+                     self.url(None, &path),
+                     &[("content-type", "application/json")],
+                     Payload::Bytes(json.as_bytes()))?;
+        Ok(())
+    }
+}
+
+fn check_status(mut response: Response, what: &str)
+    -> Result<Response, BintrayError>
+{
+    if response.status.is_success() {
+        return Ok(response);
+    }
+
+    let mut body = String::new();
+    let _ = response.read_to_string(&mut body);
+    let message = serde_json::from_str::<ErrorResponse>(&body).ok()
+        .and_then(|e| e.errors.into_iter().filter_map(|d| d.message).next());
+    Err(http::status_error(response.status, what, message))
+}
+
+fn not_found(version: &str) -> BintrayError {
+    BintrayError::from(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Version {} not found", version)))
+}
+
+impl Backend for ArtifactoryBackend {
+    fn repository_exists(&self) -> Result<bool, BintrayError> {
+        self.exists("")
+    }
+
+    fn package_exists(&self) -> Result<bool, BintrayError> {
+        self.exists(&self.package)
+    }
+
+    fn update_package(&self, _props: &PackageProps, _dry_run: bool)
+        -> Result<RecordChange, BintrayError>
+    {
+        // There is no package record: the package folder appears with
+        // the first version.
+        if self.package_exists()? {
+            Ok(RecordChange::UpToDate)
+        } else {
+            Ok(RecordChange::Created(vec![]))
+        }
+    }
+
+    fn delete_package(&self) -> Result<Option<String>, BintrayError> {
+        self.delete(&self.package)?;
+        Ok(None)
+    }
+
+    fn list_versions(&self, since: Option<&str>)
+        -> Result<Vec<VersionRecord>, BintrayError>
+    {
+        let info: FolderInfo = serde_json::from_reader(
+            self.get(self.url(Some("api/storage"), &self.package))?)
+            .map_err(BintrayError::Json)?;
+
+        // The folders are known to exist: only their metadata is read.
+        let mut versions = vec![];
+        for child in info.children.iter().filter(|c| c.folder) {
+            let version = child.uri.trim_start_matches('/');
+            let metadata = self.get_metadata(version)?.unwrap_or_default();
+            versions.push(metadata.into_record(version));
+        }
+
+        Ok(versions_since(versions, since))
+    }
+
+    fn get_version(&self, version: &str)
+        -> Result<Option<VersionRecord>, BintrayError>
+    {
+        Ok(self.read_metadata(version)?
+           .map(|metadata| metadata.into_record(version)))
+    }
+
+    fn create_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        self.write_metadata(&record.version, &VersionMetadata::new(record))
+    }
+
+    fn update_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        let mut metadata = self.read_metadata(&record.version)?
+            .ok_or_else(|| not_found(&record.version))?;
+        metadata.update(record);
+        self.write_metadata(&record.version, &metadata)
+    }
+
+    fn delete_version(&self, version: &str)
+        -> Result<Option<String>, BintrayError>
+    {
+        self.delete(&self.version_path(version)?)?;
+        Ok(None)
+    }
+
+    fn list_files(&self, version: &str, include_unpublished: bool)
+        -> Result<Vec<FileRecord>, BintrayError>
+    {
+        let listed = self.list_version(version)?;
+
+        let unpublished = listed.iter()
+            .filter_map(|f| {
+                let path = f.uri.trim_start_matches('/');
+                if path.starts_with(UNPUBLISHED_PATH) {
+                    Some(String::from(&path[UNPUBLISHED_PATH.len()..]))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<String>>();
+
+        let mut files = vec![];
+        for file in listed.iter() {
+            let path = file.uri.trim_start_matches('/');
+            if path == METADATA_PATH || path.starts_with(UNPUBLISHED_PATH) {
+                continue;
+            }
+            if !include_unpublished &&
+                unpublished.iter().any(|p| p == path) {
+                continue;
+            }
+            files.push(FileRecord {
+                path: PathBuf::from(path),
+                size: Some(file.size),
+                sha1: file.sha1.clone(),
+                sha256: file.sha2.clone(),
+            });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    fn has_unpublished_files(&self, version: &str)
+        -> Result<bool, BintrayError>
+    {
+        let listed = self.list_version(version)?;
+        Ok(listed.iter().any(|f| {
+            f.uri.trim_start_matches('/').starts_with(UNPUBLISHED_PATH)
+        }))
+    }
+
+    fn upload_file(&self,
+                   version: &str,
+                   filename: &Path,
+                   file: &FileRecord,
+                   options: &UploadOptions)
+        -> Result<Option<String>, BintrayError>
+    {
+        let path = self.file_path(version, &file.path)?;
+        if !options.override_ && self.exists(&path)? {
+            return Err(BintrayError::from(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", file.path.display()))));
+        }
+
+        // Artifactory verifies the content against the checksum headers.
+        // Content it already stores is deployed from the checksums alone.
+        let sha1 = checksum::sha1_file(filename)?;
+        let sha256 = checksum::sha256_file(filename)?;
+        let checksums = [("x-checksum-sha1", sha1.as_str()),
+                         ("x-checksum-sha256", sha256.as_str())];
+        let mut deploy_headers = checksums.to_vec();
+        deploy_headers.push(("x-checksum-deploy", "true"));
+        match self.request(Method::Put, self.url(None, &path),
+                           &deploy_headers, Payload::Empty) {
+            Ok(_) => { }
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => {
+                let mut local_file = File::open(filename)?;
+                let len = local_file.metadata()?.len();
+                self.request(Method::Put, self.url(None, &path), &checksums,
+                             Payload::File(&mut local_file, len))?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let marker_path = self.marker_path(version, &file.path)?;
+        if options.publish {
+            self.delete(&marker_path)?;
+        } else {
+            self.request(Method::Put, self.url(None, &marker_path), &[],
+                         Payload::Empty)?;
+        }
+        Ok(None)
+    }
+
+    fn download_file(&self, version: &str, file: &FileRecord, filename: &Path)
+        -> Result<(), BintrayError>
+    {
+        let path = self.file_path(version, &file.path)?;
+        let mut response = self.get(self.url(None, &path))?;
+        let mut local_file = File::create(filename)?;
+        io::copy(&mut response, &mut local_file)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, version: &str, file: &FileRecord)
+        -> Result<Option<String>, BintrayError>
+    {
+        self.delete(&self.file_path(version, &file.path)?)?;
+        self.delete(&self.marker_path(version, &file.path)?)?;
+        Ok(None)
+    }
+
+    fn publish(&self, version: &str) -> Result<usize, BintrayError> {
+        self.delete(&format!("{}{}",
+                             self.version_path(version)?,
+                             UNPUBLISHED_PATH))?;
+        Ok(0)
+    }
+
+    fn show_in_download_list(&self, _version: &str, _file: &FileRecord)
+        -> Result<(), BintrayError>
+    {
+        // There is no download list.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::test_server::TestServer;
+
+    const STORAGE: &'static str = "/artifactory/api/storage/generic/server";
+    const VERSION: &'static str = "/artifactory/generic/server/3.8.0";
+
+    fn backend(server: &TestServer) -> ArtifactoryBackend {
+        ArtifactoryBackend::new(&format!("{}/artifactory", server.url),
+                                Credentials::Token(String::from("token")),
+                                "generic",
+                                "server")
+            .unwrap()
+    }
+
+    fn upload(backend: &ArtifactoryBackend, path: &str, publish: bool) {
+        let filename = env!("CARGO_MANIFEST_DIR").to_string() + "/Cargo.toml";
+        let options = UploadOptions {
+            publish: publish,
+            override_: true,
+            debian_architecture: &[],
+            debian_distribution: &[],
+            debian_component: &[],
+        };
+        backend.upload_file("3.8.0",
+                            Path::new(&filename),
+                            &FileRecord::new(path),
+                            &options)
+            .unwrap();
+    }
+
+    #[test]
+    fn versions_are_listed_with_one_request_per_version() {
+        let server = TestServer::start();
+        server.on("GET", STORAGE, 200, r#"{"children": [
+            {"uri": "/3.7.0", "folder": true},
+            {"uri": "/3.8.0", "folder": true},
+            {"uri": "/README", "folder": false}
+        ]}"#);
+        server.on("GET", &format!("{}/.version.json", VERSION), 200,
+                  r#"{"vcs_tag": "v3.8.0"}"#);
+
+        let versions = backend(&server).list_versions(None).unwrap();
+        let versions = versions.iter()
+            .map(|v| (v.version.as_str(), v.vcs_tag.clone()))
+            .collect::<Vec<_>>();
+        // 3.7.0 has no metadata file: it's a version without properties.
+        assert_eq!(versions, vec![("3.7.0", None),
+                                  ("3.8.0", Some(String::from("v3.8.0")))]);
+        assert_eq!(server.uris("GET").len(), 3);
+    }
+
+    #[test]
+    fn files_are_listed_with_their_checksums() {
+        let server = TestServer::start();
+        server.on("GET",
+                  &format!("{}/3.8.0?list&deep=1&listFolders=0", STORAGE),
+                  200,
+                  r#"{"files": [
+            {"uri": "/.version.json", "size": 20, "folder": false},
+            {"uri": "/b.txt", "size": 2, "folder": false,
+             "sha1": "b1", "sha2": "b256"},
+            {"uri": "/.unpublished/b.txt", "size": 0, "folder": false},
+            {"uri": "/dir/a.txt", "size": 1, "folder": false,
+             "sha1": "a1", "sha2": "a256"}
+        ]}"#);
+
+        let backend = backend(&server);
+        let files = backend.list_files("3.8.0", true).unwrap();
+        assert_eq!(files, vec![
+            FileRecord {
+                path: PathBuf::from("b.txt"),
+                size: Some(2),
+                sha1: Some(String::from("b1")),
+                sha256: Some(String::from("b256")),
+            },
+            FileRecord {
+                path: PathBuf::from("dir/a.txt"),
+                size: Some(1),
+                sha1: Some(String::from("a1")),
+                sha256: Some(String::from("a256")),
+            },
+        ]);
+
+        let published = backend.list_files("3.8.0", false).unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].path, PathBuf::from("dir/a.txt"));
+
+        // Markers are found with a single listing.
+        let listings = server.uris("GET").len();
+        assert!(backend.has_unpublished_files("3.8.0").unwrap());
+        assert_eq!(server.uris("GET").len(), listings + 1);
+    }
+
+    #[test]
+    fn unknown_content_is_uploaded_after_a_checksum_deploy() {
+        let server = TestServer::start();
+        server.on("PUT", &format!("{}/file.txt", VERSION), 201, "{}");
+        server.once("PUT", &format!("{}/file.txt", VERSION), 404,
+                    r#"{"errors": [{"status": 404,
+                                    "message": "Checksum not found"}]}"#);
+        server.on("DELETE", &format!("{}/.unpublished/file.txt", VERSION),
+                  204, "");
+
+        upload(&backend(&server), "/file.txt", true);
+
+        let puts = server.requests().into_iter()
+            .filter(|r| r.method == "PUT")
+            .collect::<Vec<_>>();
+        assert_eq!(puts.len(), 2);
+        assert_eq!(puts[0].header("x-checksum-deploy"), Some("true"));
+        assert!(puts[0].body.is_empty());
+        assert_eq!(puts[1].header("x-checksum-deploy"), None);
+        assert!(puts[1].header("x-checksum-sha256").is_some());
+        assert!(!puts[1].body.is_empty());
+    }
+
+    #[test]
+    fn unpublished_files_are_marked_until_published() {
+        let server = TestServer::start();
+        server.on("PUT", &format!("{}/file.txt", VERSION), 201, "{}");
+        server.on("PUT", &format!("{}/.unpublished/file.txt", VERSION),
+                  201, "{}");
+        server.on("DELETE", &format!("{}/.unpublished", VERSION), 204, "");
+        server.on("DELETE", &format!("{}/file.txt", VERSION), 204, "");
+
+        let backend = backend(&server);
+        upload(&backend, "/file.txt", false);
+        assert_eq!(server.uris("PUT").last().map(String::as_str),
+                   Some("/artifactory/generic/server/3.8.0/.unpublished/\
+                         file.txt"));
+
+        backend.publish("3.8.0").unwrap();
+        assert_eq!(server.uris("DELETE"),
+                   vec![format!("{}/.unpublished", VERSION)]);
+
+        // Removing a file removes its marker, which may not exist.
+        backend.remove_file("3.8.0", &FileRecord::new("file.txt")).unwrap();
+        assert_eq!(server.uris("DELETE")[1..].to_vec(),
+                   vec![format!("{}/file.txt", VERSION),
+                        format!("{}/.unpublished/file.txt", VERSION)]);
+    }
+
+    #[test]
+    fn versions_are_deleted_with_their_folder() {
+        let server = TestServer::start();
+        server.on("DELETE", VERSION, 204, "");
+
+        let backend = backend(&server);
+        backend.delete_version("3.8.0").unwrap();
+        assert_eq!(server.uris("DELETE"), vec![VERSION]);
+
+        assert!(backend.delete_version("..").is_err());
+        assert!(backend.delete_version("3.8.0/..").is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+}
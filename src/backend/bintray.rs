@@ -0,0 +1,270 @@
+use backend::{Backend, FileRecord, PackageProps, RecordChange, UploadOptions,
+              VersionRecord};
+use bintray::client::{BintrayClient, BintrayError};
+use bintray::content::Content;
+use bintray::package::{Package, PackageMaturity};
+use bintray::repository::Repository;
+use bintray::version::Version;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A package in a Bintray repository.
+pub struct BintrayBackend {
+    client: BintrayClient,
+    subject: String,
+    repository: String,
+    package: String,
+    gpg_passphrase: Option<String>,
+}
+
+impl BintrayBackend {
+    pub fn new(username: &str,
+               api_key: &str,
+               subject: &str,
+               repository: &str,
+               package: &str,
+               gpg_passphrase: Option<&str>)
+        -> BintrayBackend
+    {
+        BintrayBackend {
+            client: BintrayClient::new(Some(String::from(username)),
+                                       Some(String::from(api_key))),
+            subject: String::from(subject),
+            repository: String::from(repository),
+            package: String::from(package),
+            gpg_passphrase: gpg_passphrase.map(String::from),
+        }
+    }
+
+    fn new_package(&self) -> Package {
+        Package::new(&self.subject, &self.repository, &self.package)
+    }
+
+    fn new_version(&self, version: &str) -> Version {
+        Version::new(&self.subject, &self.repository, &self.package, version)
+    }
+
+    fn new_content(&self, version: &str, file: &FileRecord) -> Content {
+        Content::new(&self.subject,
+                     &self.repository,
+                     &self.package,
+                     version,
+                     &file.path)
+    }
+
+    fn version_from_record(&self, record: &VersionRecord) -> Version {
+        let mut version = self.new_version(&record.version);
+        version.desc = record.desc.clone();
+        version.released = record.released.clone();
+        version.vcs_tag = record.vcs_tag.clone();
+        version.github_release_notes_file =
+            record.github_release_notes_file.clone();
+        version.github_use_tag_release_notes =
+            record.github_use_tag_release_notes;
+        version
+    }
+}
+
+fn version_record(version: &Version) -> VersionRecord {
+    VersionRecord {
+        version: version.version.clone(),
+        desc: version.desc.clone(),
+        released: version.released.clone(),
+        vcs_tag: version.vcs_tag.clone(),
+        github_release_notes_file: version.github_release_notes_file.clone(),
+        github_use_tag_release_notes: version.github_use_tag_release_notes,
+        created: version.created.clone(),
+        updated: version.updated.clone(),
+    }
+}
+
+fn file_record(content: Content) -> FileRecord {
+    FileRecord {
+        path: content.path,
+        size: content.size,
+        sha1: content.sha1,
+        sha256: content.sha256,
+    }
+}
+
+fn sorted(mut values: Vec<String>) -> Vec<String> {
+    values.sort();
+    values
+}
+
+impl Backend for BintrayBackend {
+    fn repository_exists(&self) -> Result<bool, BintrayError> {
+        let mut repo = Repository::new(&self.subject, &self.repository);
+        repo.exists(&self.client)
+    }
+
+    fn package_exists(&self) -> Result<bool, BintrayError> {
+        self.new_package().exists(&self.client)
+    }
+
+    fn update_package(&self, props: &PackageProps, dry_run: bool)
+        -> Result<RecordChange, BintrayError>
+    {
+        let mut package = self.new_package();
+        let exists = package.exists(&self.client)?;
+        let initial_package = package.clone();
+
+        let props = props.clone();
+        package.desc = props.desc.or(package.desc);
+        package.labels = props.labels.or(package.labels).map(sorted);
+        package.public_download_numbers = props.public_download_numbers
+            .unwrap_or(package.public_download_numbers);
+        package.public_stats = props.public_stats
+            .unwrap_or(package.public_stats);
+        package.maturity = props.maturity
+            .map_or(package.maturity, |v| Some(PackageMaturity::from(v)));
+
+        package.licenses = props.licenses.or(package.licenses).map(sorted);
+        package.custom_licenses = props.custom_licenses
+            .or(package.custom_licenses)
+            .map(sorted);
+
+        package.website_url = props.website_url.or(package.website_url);
+        package.issue_tracker_url = props.issue_tracker_url
+            .or(package.issue_tracker_url);
+        package.vcs_url = props.vcs_url.or(package.vcs_url);
+        package.github_repo = props.github_repo.or(package.github_repo);
+        package.github_release_notes_file = props.github_release_notes_file
+            .or(package.github_release_notes_file);
+
+        let changes = changed_fields!(initial_package, package,
+                                      desc, labels,
+                                      public_download_numbers, public_stats,
+                                      maturity, licenses, custom_licenses,
+                                      website_url, issue_tracker_url,
+                                      vcs_url, github_repo,
+                                      github_release_notes_file);
+        let change = match (exists, changes.is_empty()) {
+            (false, _)    => RecordChange::Created(changes),
+            (true, false) => RecordChange::Updated(changes),
+            (true, true)  => RecordChange::UpToDate,
+        };
+
+        if !dry_run {
+            match change {
+                RecordChange::Created(_) => package.create(&self.client)?,
+                RecordChange::Updated(_) => package.update(&self.client)?,
+                RecordChange::UpToDate   => { }
+            }
+        }
+
+        Ok(change)
+    }
+
+    fn delete_package(&self) -> Result<Option<String>, BintrayError> {
+        self.new_package().delete(&self.client)
+    }
+
+    fn list_versions(&self, since: Option<&str>)
+        -> Result<Vec<VersionRecord>, BintrayError>
+    {
+        let mut package = self.new_package();
+        package.get(false, &self.client)?;
+
+        let versions = package.get_versions_starting_at(
+            &since.map(String::from), Some(&self.client));
+        Ok(versions.iter().map(version_record).collect())
+    }
+
+    fn list_version_names(&self) -> Result<Vec<String>, BintrayError> {
+        let mut package = self.new_package();
+        package.get(false, &self.client)?;
+
+        // The package lists its versions from the most recent.
+        let mut versions = package.versions.clone();
+        versions.reverse();
+        Ok(versions)
+    }
+
+    fn get_version(&self, version: &str)
+        -> Result<Option<VersionRecord>, BintrayError>
+    {
+        let mut version = self.new_version(version);
+        match version.get(false, &self.client) {
+            Ok(()) => Ok(Some(version_record(&version))),
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn create_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        self.version_from_record(record).create(&self.client)
+    }
+
+    fn update_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        self.version_from_record(record).update(&self.client)
+    }
+
+    fn delete_version(&self, version: &str)
+        -> Result<Option<String>, BintrayError>
+    {
+        self.new_version(version).delete(&self.client)
+    }
+
+    fn list_files(&self, version: &str, include_unpublished: bool)
+        -> Result<Vec<FileRecord>, BintrayError>
+    {
+        let files = self.new_version(version)
+            .list_files(include_unpublished, &self.client)?;
+        Ok(files.into_iter().map(file_record).collect())
+    }
+
+    fn upload_file(&self,
+                   version: &str,
+                   filename: &Path,
+                   file: &FileRecord,
+                   options: &UploadOptions)
+        -> Result<Option<String>, BintrayError>
+    {
+        let gpg_passphrase = self.gpg_passphrase.as_ref()
+            .map(String::as_str);
+        self.new_content(version, file)
+            .upload(&PathBuf::from(filename),
+                    options.publish,
+                    options.override_,
+                    false,
+                    gpg_passphrase,
+                    options.debian_architecture,
+                    options.debian_distribution,
+                    options.debian_component,
+                    &self.client)
+    }
+
+    fn download_file(&self, version: &str, file: &FileRecord, filename: &Path)
+        -> Result<(), BintrayError>
+    {
+        self.new_content(version, file).download(filename, &self.client)
+    }
+
+    fn remove_file(&self, version: &str, file: &FileRecord)
+        -> Result<Option<String>, BintrayError>
+    {
+        self.new_content(version, file).remove(&self.client)
+    }
+
+    fn publish(&self, version: &str) -> Result<usize, BintrayError> {
+        self.new_version(version)
+            .publish_content(Some(-1), false, &self.client)
+    }
+
+    fn show_in_download_list(&self, version: &str, file: &FileRecord)
+        -> Result<(), BintrayError>
+    {
+        self.new_content(version, file)
+            .show_in_download_list(true, &self.client)
+    }
+
+    fn builds_indexes(&self) -> bool {
+        true
+    }
+}
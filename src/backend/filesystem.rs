@@ -0,0 +1,479 @@
+use backend::{Backend, FileRecord, PackageProps, RecordChange,
+              StoredChecksums, UploadOptions, VersionMetadata, VersionRecord,
+              check_version, relative_path, versions_since};
+use bintray::client::BintrayError;
+use checksum;
+use serde_json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::{thread, time};
+
+/// How long an upload waits for the lock of a sidecar. The lock is only
+/// held while the sidecar is rewritten: an older lock file was left by a
+/// process which crashed.
+const LOCK_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+/// A package stored in a local directory:
+///
+/// ```text
+/// <root>/<subject>/<repository>/<package>/<version>/<files...>
+/// <root>/<subject>/<repository>/<package>/<version>.json
+/// ```
+///
+/// The JSON sidecar holds the version properties, the list of files
+/// which are uploaded but not published yet and the checksums of the
+/// uploaded files. Package properties are not stored.
+pub struct FilesystemBackend {
+    repository_dir: PathBuf,
+    package_dir: PathBuf,
+}
+
+impl FilesystemBackend {
+    pub fn new<P: AsRef<Path>>(root: P,
+                               subject: &str,
+                               repository: &str,
+                               package: &str)
+        -> FilesystemBackend
+    {
+        let repository_dir = root.as_ref().join(subject).join(repository);
+        let package_dir = repository_dir.join(package);
+        FilesystemBackend {
+            repository_dir: repository_dir,
+            package_dir: package_dir,
+        }
+    }
+
+    /// Fails if the version would point outside of the package.
+    fn version_dir(&self, version: &str) -> Result<PathBuf, BintrayError> {
+        check_version(version)?;
+        Ok(self.package_dir.join(version))
+    }
+
+    fn sidecar_path(&self, version: &str) -> Result<PathBuf, BintrayError> {
+        check_version(version)?;
+        Ok(self.package_dir.join(format!("{}.json", version)))
+    }
+
+    fn file_path(&self, version: &str, file: &FileRecord)
+        -> Result<PathBuf, BintrayError>
+    {
+        Ok(self.version_dir(version)?.join(relative_path(&file.path)))
+    }
+
+    /// Returns `None` if neither the version directory nor the sidecar
+    /// exist. A version directory without a sidecar (e.g. populated by
+    /// hand) is a version without properties.
+    fn read_sidecar(&self, version: &str)
+        -> Result<Option<VersionMetadata>, BintrayError>
+    {
+        match File::open(self.sidecar_path(version)?) {
+            Ok(file) => {
+                let sidecar = serde_json::from_reader(BufReader::new(file))
+                    .map_err(BintrayError::Json)?;
+                Ok(Some(sidecar))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                if self.version_dir(version)?.is_dir() {
+                    Ok(Some(VersionMetadata::default()))
+                } else {
+                    Ok(None)
+                }
+            }
+            Err(e) => Err(BintrayError::from(e)),
+        }
+    }
+
+    fn write_sidecar(&self, version: &str, sidecar: &VersionMetadata)
+        -> Result<(), BintrayError>
+    {
+        // Write to a temporary file first so readers never see a partial
+        // sidecar.
+        let path = self.sidecar_path(version)?;
+        let tmp_path = self.package_dir.join(format!(".{}.json.tmp", version));
+        {
+            let file = File::create(&tmp_path)?;
+            serde_json::to_writer_pretty(&mut BufWriter::new(file), sidecar)
+                .map_err(BintrayError::Json)?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    /// Reads, modifies and writes back the sidecar of an existing version
+    /// while holding its lock file. Uploads of the same version may run
+    /// concurrently, from this process or another one sharing the
+    /// directory.
+    fn modify_sidecar<F>(&self, version: &str, f: F)
+        -> Result<(), BintrayError>
+        where F: FnOnce(&mut VersionMetadata)
+    {
+        check_version(version)?;
+        let lock_path = self.package_dir.join(format!(".{}.lock", version));
+        let start = time::Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true)
+                .open(&lock_path) {
+                Ok(_) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if is_stale(&lock_path) {
+                        let _ = fs::remove_file(&lock_path);
+                        continue;
+                    }
+                    if start.elapsed() >= LOCK_TIMEOUT {
+                        return Err(BintrayError::from(io::Error::new(
+                                    io::ErrorKind::AlreadyExists,
+                                    format!("{} is still locked after {}s; \
+                                             remove it if no upload of \
+                                             version {} is running",
+                                            lock_path.display(),
+                                            LOCK_TIMEOUT.as_secs(),
+                                            version))));
+                    }
+                    thread::sleep(time::Duration::from_millis(10));
+                }
+                Err(e) => return Err(BintrayError::from(e)),
+            }
+        }
+
+        let result = self.read_sidecar(version).and_then(|sidecar| {
+            let mut sidecar = sidecar.ok_or_else(|| not_found(version))?;
+            f(&mut sidecar);
+            self.write_sidecar(version, &sidecar)
+        });
+        let _ = fs::remove_file(&lock_path);
+        result
+    }
+}
+
+/// Tells if the lock file was created before the lock timeout.
+fn is_stale(lock_path: &Path) -> bool {
+    fs::metadata(lock_path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map_or(false, |age| age >= LOCK_TIMEOUT)
+}
+
+/// Identifies the content of a stored file without reading it: a file
+/// rewritten since its checksums were recorded has another size or
+/// modification time. Returns `None` if the modification time isn't
+/// available.
+fn file_tag(metadata: &fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?
+        .duration_since(time::UNIX_EPOCH).ok()?;
+    Some(format!("{}-{}.{:09}",
+                 metadata.len(),
+                 modified.as_secs(),
+                 modified.subsec_nanos()))
+}
+
+fn not_found(version: &str) -> BintrayError {
+    BintrayError::from(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Version {} not found", version)))
+}
+
+/// Lists regular files below `dir`, relative to `base`.
+fn walk_dir(base: &Path, dir: &Path, files: &mut Vec<PathBuf>)
+    -> io::Result<()>
+{
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk_dir(base, &path, files)?;
+        } else if path.is_file() {
+            files.push(PathBuf::from(path.strip_prefix(base).unwrap()));
+        }
+    }
+    Ok(())
+}
+
+impl Backend for FilesystemBackend {
+    fn repository_exists(&self) -> Result<bool, BintrayError> {
+        Ok(self.repository_dir.is_dir())
+    }
+
+    fn package_exists(&self) -> Result<bool, BintrayError> {
+        Ok(self.package_dir.is_dir())
+    }
+
+    fn update_package(&self, _props: &PackageProps, dry_run: bool)
+        -> Result<RecordChange, BintrayError>
+    {
+        if self.package_dir.is_dir() {
+            return Ok(RecordChange::UpToDate);
+        }
+        if !dry_run {
+            fs::create_dir(&self.package_dir)?;
+        }
+        Ok(RecordChange::Created(vec![]))
+    }
+
+    fn delete_package(&self) -> Result<Option<String>, BintrayError> {
+        fs::remove_dir_all(&self.package_dir)?;
+        Ok(None)
+    }
+
+    fn list_versions(&self, since: Option<&str>)
+        -> Result<Vec<VersionRecord>, BintrayError>
+    {
+        let mut versions = vec![];
+        for entry in fs::read_dir(&self.package_dir)? {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let version = match path.file_name().and_then(|n| n.to_str()) {
+                Some(version) => String::from(version),
+                None          => continue,
+            };
+            match self.read_sidecar(&version)? {
+                Some(sidecar) => versions.push(sidecar.into_record(&version)),
+                None          => { }
+            }
+        }
+
+        Ok(versions_since(versions, since))
+    }
+
+    fn get_version(&self, version: &str)
+        -> Result<Option<VersionRecord>, BintrayError>
+    {
+        Ok(self.read_sidecar(version)?
+           .map(|sidecar| sidecar.into_record(version)))
+    }
+
+    fn create_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        fs::create_dir_all(self.version_dir(&record.version)?)?;
+        self.write_sidecar(&record.version, &VersionMetadata::new(record))
+    }
+
+    fn update_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        self.modify_sidecar(&record.version,
+                            |sidecar| sidecar.update(record))
+    }
+
+    fn delete_version(&self, version: &str)
+        -> Result<Option<String>, BintrayError>
+    {
+        match fs::remove_dir_all(self.version_dir(version)?) {
+            Ok(()) => { }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => { }
+            Err(e) => return Err(BintrayError::from(e)),
+        }
+        match fs::remove_file(self.sidecar_path(version)?) {
+            Ok(()) => Ok(None),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(BintrayError::from(e)),
+        }
+    }
+
+    fn list_files(&self, version: &str, include_unpublished: bool)
+        -> Result<Vec<FileRecord>, BintrayError>
+    {
+        let sidecar = self.read_sidecar(version)?
+            .ok_or_else(|| not_found(version))?;
+
+        let version_dir = self.version_dir(version)?;
+        let mut paths = vec![];
+        walk_dir(&version_dir, &version_dir, &mut paths)?;
+        paths.sort();
+
+        let mut files = vec![];
+        for path in paths {
+            if !include_unpublished && sidecar.unpublished.contains(&path) {
+                continue;
+            }
+            let filename = version_dir.join(&path);
+            let metadata = fs::metadata(&filename)?;
+
+            // Checksums are recorded by `upload_file()`. Files changed
+            // since then, or copied by hand, are read.
+            let tag = file_tag(&metadata);
+            let (sha1, sha256) = match sidecar.checksums
+                .get(&*path.to_string_lossy())
+                .filter(|c| tag.as_ref() == Some(&c.etag)) {
+                Some(c) => (c.sha1.clone(), c.sha256.clone()),
+                None    => (checksum::sha1_file(&filename)?,
+                            checksum::sha256_file(&filename)?),
+            };
+            files.push(FileRecord {
+                size: Some(metadata.len()),
+                sha1: Some(sha1),
+                sha256: Some(sha256),
+                path: path,
+            });
+        }
+        Ok(files)
+    }
+
+    fn has_unpublished_files(&self, version: &str)
+        -> Result<bool, BintrayError>
+    {
+        let sidecar = self.read_sidecar(version)?
+            .ok_or_else(|| not_found(version))?;
+        Ok(!sidecar.unpublished.is_empty())
+    }
+
+    fn upload_file(&self,
+                   version: &str,
+                   filename: &Path,
+                   file: &FileRecord,
+                   options: &UploadOptions)
+        -> Result<Option<String>, BintrayError>
+    {
+        let path = relative_path(&file.path);
+        let dest = self.version_dir(version)?.join(&path);
+        if !options.override_ && dest.exists() {
+            return Err(BintrayError::from(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", path.display()))));
+        }
+
+        match dest.parent() {
+            Some(parent) => { fs::create_dir_all(parent)?; }
+            None         => { }
+        }
+        fs::copy(filename, &dest)?;
+        let checksums = match file_tag(&fs::metadata(&dest)?) {
+            Some(tag) => Some(StoredChecksums {
+                etag: tag,
+                sha1: checksum::sha1_file(&dest)?,
+                sha256: checksum::sha256_file(&dest)?,
+            }),
+            None => None,
+        };
+
+        let publish = options.publish;
+        let key = path.to_string_lossy().into_owned();
+        self.modify_sidecar(version, |sidecar| {
+            sidecar.unpublished.retain(|p| *p != path);
+            if !publish {
+                sidecar.unpublished.push(path.clone());
+            }
+            match checksums {
+                Some(checksums) => {
+                    sidecar.checksums.insert(key, checksums);
+                }
+                None => { sidecar.checksums.remove(&key); }
+            }
+        })?;
+        Ok(None)
+    }
+
+    fn download_file(&self, version: &str, file: &FileRecord, filename: &Path)
+        -> Result<(), BintrayError>
+    {
+        fs::copy(self.file_path(version, file)?, filename)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, version: &str, file: &FileRecord)
+        -> Result<Option<String>, BintrayError>
+    {
+        let path = relative_path(&file.path);
+        fs::remove_file(self.version_dir(version)?.join(&path))?;
+        let key = path.to_string_lossy().into_owned();
+        self.modify_sidecar(version, |sidecar| {
+            sidecar.unpublished.retain(|p| *p != path);
+            sidecar.checksums.remove(&key);
+        })?;
+        Ok(None)
+    }
+
+    fn publish(&self, version: &str) -> Result<usize, BintrayError> {
+        self.modify_sidecar(version, |sidecar| {
+            sidecar.unpublished.clear();
+        })?;
+        Ok(0)
+    }
+
+    fn show_in_download_list(&self, _version: &str, _file: &FileRecord)
+        -> Result<(), BintrayError>
+    {
+        // There is no download list.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use temp_dir::TempDir;
+
+    fn backend(root: &Path) -> FilesystemBackend {
+        fs::create_dir_all(root.join("team/generic")).unwrap();
+        let backend = FilesystemBackend::new(root, "team", "generic", "pkg");
+        backend.update_package(&PackageProps::default(), false).unwrap();
+        backend.create_version(&VersionRecord::new("3.8.0")).unwrap();
+        backend
+    }
+
+    fn upload(backend: &FilesystemBackend, root: &Path, path: &str) {
+        let filename = root.join("upload");
+        fs::write(&filename, path.as_bytes()).unwrap();
+        let options = UploadOptions {
+            publish: true,
+            override_: true,
+            debian_architecture: &[],
+            debian_distribution: &[],
+            debian_component: &[],
+        };
+        backend.upload_file("3.8.0", &filename, &FileRecord::new(path),
+                            &options)
+            .unwrap();
+    }
+
+    #[test]
+    fn uploads_record_checksums_until_the_file_changes() {
+        let root = TempDir::new("bintray-package-test").unwrap();
+        let backend = backend(root.path());
+        upload(&backend, root.path(), "a.txt");
+
+        let sidecar = backend.read_sidecar("3.8.0").unwrap().unwrap();
+        let stored = sidecar.checksums["a.txt"].clone();
+        assert_eq!(stored.sha256, checksum::sha256_file(
+                root.path().join("upload")).unwrap());
+
+        // Listed checksums come from the sidecar...
+        let mut sidecar = sidecar;
+        sidecar.checksums.get_mut("a.txt").unwrap().sha1 =
+            String::from("recorded");
+        backend.write_sidecar("3.8.0", &sidecar).unwrap();
+        let files = backend.list_files("3.8.0", true).unwrap();
+        assert_eq!(files[0].sha1.as_ref().map(String::as_str),
+                   Some("recorded"));
+
+        // ...unless the file was changed by another tool.
+        let filename = root.path().join("team/generic/pkg/3.8.0/a.txt");
+        OpenOptions::new().append(true).open(&filename).unwrap()
+            .write_all(b" changed").unwrap();
+        let files = backend.list_files("3.8.0", true).unwrap();
+        assert_eq!(files[0].sha1,
+                   Some(checksum::sha1_file(&filename).unwrap()));
+
+        backend.remove_file("3.8.0", &FileRecord::new("a.txt")).unwrap();
+        let sidecar = backend.read_sidecar("3.8.0").unwrap().unwrap();
+        assert!(sidecar.checksums.is_empty());
+    }
+
+    #[test]
+    fn locks_left_by_crashed_processes_are_broken() {
+        let root = TempDir::new("bintray-package-test").unwrap();
+        let backend = backend(root.path());
+        let lock_path = root.path().join("team/generic/pkg/.3.8.0.lock");
+        let lock = File::create(&lock_path).unwrap();
+        assert!(!is_stale(&lock_path));
+
+        lock.set_modified(time::SystemTime::now() - LOCK_TIMEOUT).unwrap();
+        assert!(is_stale(&lock_path));
+        upload(&backend, root.path(), "a.txt");
+        assert!(!lock_path.exists());
+    }
+}
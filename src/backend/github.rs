@@ -0,0 +1,913 @@
+use backend::{Backend, FileRecord, PackageProps, RecordChange, UploadOptions,
+              VersionRecord, http, relative_path, versions_since};
+use bintray::client::BintrayError;
+use hyper::client::{Body, Client, Response};
+use hyper::header::{Headers, Location};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use hyper::Url;
+use serde_json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Number of releases per page when listing releases.
+const PER_PAGE: usize = 100;
+
+/// Maximum number of redirections followed when downloading an asset.
+const MAX_REDIRECTS: usize = 5;
+
+/// The releases of a GitHub repository:
+///
+/// * `subject` and `repository` are the owner and name of the GitHub
+///   repository; `package` is not part of the mapping.
+/// * A version is a release named after it. The release is tagged with
+///   the version's `vcs_tag`, or the version itself.
+/// * Files are release assets. Assets can't be in directories, so they
+///   are named after the file name and labelled with the full path.
+/// * Releases are created as drafts: all their assets are unpublished
+///   until the release is published.
+///
+/// Finding the release of a version means listing all the releases, page
+/// by page. The ids of the releases listed are kept, so each operation on
+/// a known version then gets its release with a single request.
+pub struct GitHubBackend {
+    client: Client,
+    endpoint: Url,
+    token: Option<String>,
+    repo_path: String,
+    release_ids: Mutex<HashMap<String, u64>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    id: u64,
+    tag_name: String,
+    name: Option<String>,
+    body: Option<String>,
+    draft: bool,
+    created_at: Option<String>,
+    published_at: Option<String>,
+    #[serde(default)]
+    updated_at: Option<String>,
+    upload_url: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    id: u64,
+    name: String,
+    label: Option<String>,
+    size: u64,
+    state: String,
+    #[serde(default)]
+    digest: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ReleaseChange {
+    #[serde(skip_serializing_if="Option::is_none")]
+    tag_name: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    body: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    draft: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    message: Option<String>,
+    #[serde(default)]
+    errors: Vec<ErrorDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    code: Option<String>,
+}
+
+impl Release {
+    fn version(&self) -> &str {
+        match self.name {
+            Some(ref name) if !name.is_empty() => name,
+            _                                  => &self.tag_name,
+        }
+    }
+
+    fn record(&self) -> VersionRecord {
+        let (desc, notes_file) = match self.body {
+            Some(ref body) => split_release_body(body),
+            None           => (None, None),
+        };
+        VersionRecord {
+            version: String::from(self.version()),
+            desc: desc,
+            released: self.published_at.clone(),
+            vcs_tag: Some(self.tag_name.clone()),
+            github_release_notes_file: notes_file,
+            github_use_tag_release_notes: None,
+            created: self.created_at.clone(),
+            updated: self.updated_at.clone()
+                .or(self.published_at.clone())
+                .or(self.created_at.clone()),
+        }
+    }
+
+    fn find_asset(&self, path: &Path) -> Option<&Asset> {
+        let path = relative_path(path);
+        self.assets.iter().find(|a| relative_path(&a.path()) == path)
+    }
+}
+
+impl Asset {
+    fn path(&self) -> PathBuf {
+        match self.label {
+            Some(ref label) if !label.is_empty() => PathBuf::from(label),
+            _                                    => PathBuf::from(&self.name),
+        }
+    }
+}
+
+impl GitHubBackend {
+    /// Without an `endpoint`, the public GitHub API is used. Without a
+    /// `token`, only public repositories can be read.
+    pub fn new(endpoint: Option<&str>,
+               token: Option<&str>,
+               owner: &str,
+               repository: &str)
+        -> Result<GitHubBackend, BintrayError>
+    {
+        let endpoint = endpoint.unwrap_or("https://api.github.com");
+        let endpoint = Url::parse(endpoint)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput,
+                                        format!("{}: {}", endpoint, e)))?;
+
+        Ok(GitHubBackend {
+            client: http::new_client()?,
+            endpoint: endpoint,
+            token: token.map(String::from),
+            repo_path: format!("repos/{}/{}", owner, repository),
+            release_ids: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn url(&self, path: &str) -> Url {
+        let mut url = self.endpoint.clone();
+        let base = url.path().trim_end_matches('/').to_string();
+        url.set_path(&format!("{}/{}/{}", base, self.repo_path, path)
+                     .trim_end_matches('/'));
+        url
+    }
+
+    fn headers(&self, accept: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("accept", vec![accept.as_bytes().to_vec()]);
+        headers.set_raw("user-agent",
+                        vec![b"concourse-bintray-resources".to_vec()]);
+        headers.set_raw("x-github-api-version", vec![b"2022-11-28".to_vec()]);
+        match self.token {
+            Some(ref token) => {
+                headers.set_raw("authorization",
+                                vec![format!("Bearer {}", token).into_bytes()]);
+            }
+            None => { }
+        }
+        headers
+    }
+
+    /// Sends a request to the API and returns the response if its status
+    /// is a success.
+    fn request(&self, method: Method, url: Url, json: Option<&str>)
+        -> Result<Response, BintrayError>
+    {
+        let what = format!("{} {}", method, url);
+        let mut headers = self.headers("application/vnd.github+json");
+        let builder = self.client.request(method, url);
+        let response = match json {
+            Some(json) => {
+                headers.set_raw("content-type",
+                                vec![b"application/json".to_vec()]);
+                builder.headers(headers)
+                    .body(Body::BufBody(json.as_bytes(), json.len()))
+                    .send()
+            }
+            None => builder.headers(headers).send(),
+        };
+        let response = response.map_err(|e| http::request_error(e, &what))?;
+        check_status(response, &what)
+    }
+
+    fn list_releases(&self) -> Result<Vec<Release>, BintrayError> {
+        let mut releases = vec![];
+        let mut page = 1;
+        loop {
+            let mut url = self.url("releases");
+            url.query_pairs_mut()
+                .append_pair("per_page", &PER_PAGE.to_string())
+                .append_pair("page", &page.to_string());
+            let response = self.request(Method::Get, url, None)?;
+            let mut batch: Vec<Release> = serde_json::from_reader(response)
+                .map_err(BintrayError::Json)?;
+            let count = batch.len();
+            releases.append(&mut batch);
+            if count < PER_PAGE {
+                break;
+            }
+            page += 1;
+        }
+
+        let mut release_ids = self.release_ids.lock().unwrap();
+        release_ids.clear();
+        for release in releases.iter() {
+            release_ids.insert(String::from(release.version()), release.id);
+        }
+        Ok(releases)
+    }
+
+    /// Returns `None` if there is no release for `version`. Releases are
+    /// listed rather than looked up by tag: draft releases can't be. Once
+    /// listed, a release is looked up by id, unless it was deleted or
+    /// renamed in the meantime.
+    fn find_release(&self, version: &str)
+        -> Result<Option<Release>, BintrayError>
+    {
+        let id = self.release_ids.lock().unwrap().get(version).cloned();
+        match id {
+            Some(id) => {
+                let url = self.url(&format!("releases/{}", id));
+                match self.request(Method::Get, url, None) {
+                    Ok(response) => {
+                        let release: Release =
+                            serde_json::from_reader(response)
+                            .map_err(BintrayError::Json)?;
+                        if release.version() == version {
+                            return Ok(Some(release));
+                        }
+                    }
+                    Err(BintrayError::Io(ref e))
+                        if e.kind() == io::ErrorKind::NotFound => { }
+                    Err(e) => return Err(e),
+                }
+            }
+            None => { }
+        }
+
+        Ok(self.list_releases()?
+           .into_iter()
+           .find(|r| r.version() == version))
+    }
+
+    fn get_release(&self, version: &str) -> Result<Release, BintrayError> {
+        self.find_release(version)?.ok_or_else(|| not_found(version))
+    }
+
+    fn edit_release(&self, release: &Release, change: &ReleaseChange)
+        -> Result<(), BintrayError>
+    {
+        let json = serde_json::to_string(change)
+            .map_err(BintrayError::Json)?;
+        self.request(Method::Patch,
+                     self.url(&format!("releases/{}", release.id)),
+                     Some(&json))?;
+        Ok(())
+    }
+
+    fn delete_asset(&self, asset: &Asset) -> Result<(), BintrayError> {
+        self.request(Method::Delete,
+                     self.url(&format!("releases/assets/{}", asset.id)),
+                     None)?;
+        Ok(())
+    }
+}
+
+fn check_status(mut response: Response, what: &str)
+    -> Result<Response, BintrayError>
+{
+    if response.status.is_success() {
+        return Ok(response);
+    }
+
+    let mut body = String::new();
+    let _ = response.read_to_string(&mut body);
+    let error = serde_json::from_str::<ErrorResponse>(&body).ok();
+    let message = error.as_ref().and_then(|e| e.message.clone());
+
+    // Exceeded rate limits are transient, and duplicate assets are
+    // reported as validation errors.
+    let rate_limited = response.status == StatusCode::Forbidden &&
+        header_value(&response, "x-ratelimit-remaining") ==
+        Some(String::from("0"));
+    let already_exists = error.as_ref().map_or(false, |e| {
+        e.errors.iter()
+            .any(|d| d.code.as_ref().map_or(false, |c| c == "already_exists"))
+    });
+    let kind = match (rate_limited, already_exists) {
+        (true, _)      => io::ErrorKind::Other,
+        (false, true)  => io::ErrorKind::AlreadyExists,
+        (false, false) => {
+            return Err(http::status_error(response.status, what, message));
+        }
+    };
+    Err(BintrayError::from(io::Error::new(
+                kind,
+                format!("{}: {}: {}",
+                        what, response.status, message.unwrap_or_default()))))
+}
+
+fn header_value(response: &Response, name: &str) -> Option<String> {
+    response.headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+}
+
+fn not_found(version: &str) -> BintrayError {
+    BintrayError::from(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Release {} not found", version)))
+}
+
+/// Starts the comment separating the description from the release notes
+/// in a release body.
+const NOTES_MARKER: &'static str = "<!-- github_release_notes_file: ";
+
+/// The release body is the version description followed by the content
+/// of the release notes file, read from the working directory. A comment
+/// naming the file separates them, so `split_release_body()` can tell the
+/// description apart from the notes.
+fn release_body(record: &VersionRecord) -> Result<Option<String>, BintrayError> {
+    let filename = match record.github_release_notes_file {
+        Some(ref filename) => filename,
+        None               => return Ok(record.desc.clone()),
+    };
+    let mut notes = String::new();
+    File::open(filename)
+        .and_then(|mut f| f.read_to_string(&mut notes))
+        .map_err(|e| io::Error::new(e.kind(),
+                                    format!("{}: {}", filename, e)))?;
+
+    let mut body = String::new();
+    match record.desc {
+        Some(ref desc) => {
+            body.push_str(desc);
+            body.push_str("\n\n");
+        }
+        None => { }
+    }
+    body.push_str(&format!("{}{} -->\n{}", NOTES_MARKER, filename, notes));
+    Ok(Some(body))
+}
+
+/// Returns the description and the release notes file of a body written
+/// by `release_body()`. Bodies without notes are descriptions.
+fn split_release_body(body: &str) -> (Option<String>, Option<String>) {
+    let start = match body.find(NOTES_MARKER) {
+        Some(start) => start,
+        None        => return (Some(String::from(body)), None),
+    };
+    let marker = &body[start + NOTES_MARKER.len()..];
+    let filename = match marker.find(" -->") {
+        Some(end) => String::from(&marker[..end]),
+        None      => return (Some(String::from(body)), None),
+    };
+    let desc = if start == 0 {
+        None
+    } else {
+        let desc = &body[..start];
+        Some(String::from(if desc.ends_with("\n\n") {
+            &desc[..desc.len() - 2]
+        } else {
+            desc
+        }))
+    };
+    (desc, Some(filename))
+}
+
+impl Backend for GitHubBackend {
+    fn repository_exists(&self) -> Result<bool, BintrayError> {
+        match self.request(Method::Get, self.url(""), None) {
+            Ok(_) => Ok(true),
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn package_exists(&self) -> Result<bool, BintrayError> {
+        // The package is the repository itself.
+        self.repository_exists()
+    }
+
+    fn update_package(&self, _props: &PackageProps, _dry_run: bool)
+        -> Result<RecordChange, BintrayError>
+    {
+        // Package properties are those of the GitHub repository, which
+        // are not managed here.
+        if self.repository_exists()? {
+            Ok(RecordChange::UpToDate)
+        } else {
+            Err(BintrayError::from(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Repository {} not found", self.repo_path))))
+        }
+    }
+
+    fn delete_package(&self) -> Result<Option<String>, BintrayError> {
+        Err(BintrayError::from(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Deleting a package is not supported by the \
+                     github_releases backend; delete its versions instead")))
+    }
+
+    fn list_versions(&self, since: Option<&str>)
+        -> Result<Vec<VersionRecord>, BintrayError>
+    {
+        let versions = self.list_releases()?
+            .iter()
+            .map(Release::record)
+            .collect();
+        Ok(versions_since(versions, since))
+    }
+
+    fn get_version(&self, version: &str)
+        -> Result<Option<VersionRecord>, BintrayError>
+    {
+        Ok(self.find_release(version)?.map(|r| r.record()))
+    }
+
+    fn create_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        let change = ReleaseChange {
+            tag_name: Some(record.vcs_tag.clone()
+                           .unwrap_or_else(|| record.version.clone())),
+            name: Some(record.version.clone()),
+            body: release_body(record)?,
+            draft: Some(true),
+        };
+        let json = serde_json::to_string(&change)
+            .map_err(BintrayError::Json)?;
+        let response = self.request(Method::Post,
+                                    self.url("releases"),
+                                    Some(&json))?;
+        let release: Release = serde_json::from_reader(response)
+            .map_err(BintrayError::Json)?;
+        self.release_ids.lock().unwrap()
+            .insert(record.version.clone(), release.id);
+        Ok(())
+    }
+
+    fn update_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        let release = self.get_release(&record.version)?;
+        let change = ReleaseChange {
+            tag_name: record.vcs_tag.clone(),
+            body: release_body(record)?,
+            ..ReleaseChange::default()
+        };
+        self.edit_release(&release, &change)
+    }
+
+    fn delete_version(&self, version: &str)
+        -> Result<Option<String>, BintrayError>
+    {
+        // The tag is kept: it may predate the release.
+        let release = self.get_release(version)?;
+        self.request(Method::Delete,
+                     self.url(&format!("releases/{}", release.id)),
+                     None)?;
+        self.release_ids.lock().unwrap().remove(version);
+        Ok(None)
+    }
+
+    fn list_files(&self, version: &str, include_unpublished: bool)
+        -> Result<Vec<FileRecord>, BintrayError>
+    {
+        let release = self.get_release(version)?;
+        if release.draft && !include_unpublished {
+            return Ok(vec![]);
+        }
+
+        let mut files = release.assets.iter()
+            // Other states are failed or ongoing uploads.
+            .filter(|a| a.state == "uploaded")
+            .map(|a| FileRecord {
+                path: a.path(),
+                size: Some(a.size),
+                sha1: None,
+                sha256: a.digest.as_ref()
+                    .and_then(|d| {
+                        if d.starts_with("sha256:") {
+                            Some(String::from(&d["sha256:".len()..]))
+                        } else {
+                            None
+                        }
+                    }),
+            })
+            .collect::<Vec<FileRecord>>();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    fn has_unpublished_files(&self, version: &str)
+        -> Result<bool, BintrayError>
+    {
+        // The assets of a draft release are its unpublished files.
+        let release = self.get_release(version)?;
+        Ok(release.draft &&
+           release.assets.iter().any(|a| a.state == "uploaded"))
+    }
+
+    fn upload_file(&self,
+                   version: &str,
+                   filename: &Path,
+                   file: &FileRecord,
+                   options: &UploadOptions)
+        -> Result<Option<String>, BintrayError>
+    {
+        // Whether assets are published depends on the release only.
+        let release = self.get_release(version)?;
+        match release.find_asset(&file.path) {
+            Some(_) if !options.override_ => {
+                return Err(BintrayError::from(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} already exists",
+                                    file.path.display()))));
+            }
+            Some(asset) => self.delete_asset(asset)?,
+            None        => { }
+        }
+
+        let path = relative_path(&file.path);
+        let name = path.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        // The upload URL is a URI template such as
+        // `https://uploads.github.com/.../assets{?name,label}`.
+        let upload_url = release.upload_url.split('{').next().unwrap_or("");
+        let mut url = Url::parse(upload_url)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                                        format!("{}: {}", upload_url, e)))?;
+        url.query_pairs_mut()
+            .append_pair("name", &name)
+            .append_pair("label", &path.to_string_lossy());
+
+        let mut local_file = File::open(filename)?;
+        let len = local_file.metadata()?.len();
+        let what = format!("POST {}", url);
+        let mut headers = self.headers("application/vnd.github+json");
+        headers.set_raw("content-type",
+                        vec![b"application/octet-stream".to_vec()]);
+        let response = self.client.request(Method::Post, url)
+            .headers(headers)
+            .body(Body::SizedBody(&mut local_file, len))
+            .send()
+            .map_err(|e| http::request_error(e, &what))?;
+        check_status(response, &what)?;
+        Ok(None)
+    }
+
+    fn is_upload_idempotent(&self, _options: &UploadOptions) -> bool {
+        // Assets are uploaded with a POST: the asset of an upload applied
+        // after a timeout may not be listed yet when it's retried.
+        false
+    }
+
+    fn download_file(&self, version: &str, file: &FileRecord, filename: &Path)
+        -> Result<(), BintrayError>
+    {
+        let release = self.get_release(version)?;
+        let asset = release.find_asset(&file.path)
+            .ok_or_else(|| io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} not found", file.path.display())))?;
+
+        // The API redirects to a pre-signed URL which must be requested
+        // without the token.
+        let mut url = self.url(&format!("releases/assets/{}", asset.id));
+        let mut headers = self.headers("application/octet-stream");
+        let mut redirects = 0;
+        let mut response = loop {
+            let what = format!("GET {}", url);
+            let response = self.client.request(Method::Get, url.clone())
+                .headers(headers.clone())
+                .send()
+                .map_err(|e| http::request_error(e, &what))?;
+            let location = match response.headers.get::<Location>() {
+                Some(location) if response.status.is_redirection() => {
+                    String::from(&**location)
+                }
+                _ => break check_status(response, &what)?,
+            };
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                return Err(BintrayError::from(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("{}: too many redirections", what))));
+            }
+            url = url.join(&location)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                                            format!("{}: {}", location, e)))?;
+            headers.remove_raw("authorization");
+        };
+
+        let mut local_file = File::create(filename)?;
+        io::copy(&mut response, &mut local_file)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, version: &str, file: &FileRecord)
+        -> Result<Option<String>, BintrayError>
+    {
+        let release = self.get_release(version)?;
+        match release.find_asset(&file.path) {
+            Some(asset) => self.delete_asset(asset)?,
+            None        => { }
+        }
+        Ok(None)
+    }
+
+    fn publish(&self, version: &str) -> Result<usize, BintrayError> {
+        let release = self.get_release(version)?;
+        if release.draft {
+            let change = ReleaseChange {
+                draft: Some(false),
+                ..ReleaseChange::default()
+            };
+            self.edit_release(&release, &change)?;
+        }
+        Ok(0)
+    }
+
+    fn show_in_download_list(&self, _version: &str, _file: &FileRecord)
+        -> Result<(), BintrayError>
+    {
+        // Assets of a published release are always listed.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::test_server::TestServer;
+    use std::{env, fs, process};
+    use temp_dir::TempDir;
+
+    const RELEASES: &'static str = "/repos/rabbitmq/server/releases";
+
+    fn backend(server: &TestServer) -> GitHubBackend {
+        GitHubBackend::new(Some(&server.url), Some("token"),
+                           "rabbitmq", "server")
+            .unwrap()
+    }
+
+    fn release(server: &TestServer, id: u64, version: &str, draft: bool,
+               assets: &str)
+        -> String
+    {
+        format!(r#"{{
+            "id": {id}, "tag_name": "v{version}", "name": "{version}",
+            "body": null, "draft": {draft},
+            "created_at": "2017-05-01T10:00:00Z", "published_at": null,
+            "upload_url": "{url}/uploads/{id}/assets{{?name,label}}",
+            "assets": [{assets}]
+        }}"#,
+                id = id, version = version, draft = draft, url = server.url,
+                assets = assets)
+    }
+
+    fn release_with_body(server: &TestServer, id: u64, version: &str,
+                         body: &str)
+        -> String
+    {
+        release(server, id, version, false, "")
+            .replace("\"body\": null",
+                     &format!("\"body\": {}",
+                              serde_json::to_string(body).unwrap()))
+    }
+
+    fn error_kind<T>(result: Result<T, BintrayError>) -> io::ErrorKind {
+        match result {
+            Err(BintrayError::Io(e)) => e.kind(),
+            Err(e)                   => panic!("unexpected error: {}", e),
+            Ok(_)                    => panic!("unexpected success"),
+        }
+    }
+
+    fn options(override_: bool) -> UploadOptions<'static> {
+        UploadOptions {
+            publish: false,
+            override_: override_,
+            debian_architecture: &[],
+            debian_distribution: &[],
+            debian_component: &[],
+        }
+    }
+
+    #[test]
+    fn release_notes_are_kept_apart_from_the_description() {
+        let server = TestServer::start();
+        server.on("PATCH", &format!("{}/7", RELEASES), 200, "{}");
+        let dir = TempDir::new("bintray-package-test").unwrap();
+        let notes = dir.path().join("NOTES.md");
+        fs::write(&notes, "* Bug fixes\n").unwrap();
+        let notes = notes.to_string_lossy().into_owned();
+
+        let backend = backend(&server);
+        let mut body = String::from("RabbitMQ 3.8.0");
+        let mut bodies = vec![];
+        for _ in 0..2 {
+            // Like `out`, each put starts from the stored release and
+            // sets the release notes file.
+            let stored = release_with_body(&server, 7, "3.8.0", &body);
+            server.on("GET", RELEASES, 200, &format!("[{}]", stored));
+            server.on("GET", &format!("{}/7", RELEASES), 200, &stored);
+            let mut record = backend.get_version("3.8.0").unwrap().unwrap();
+            record.github_release_notes_file = Some(notes.clone());
+            backend.update_version(&record).unwrap();
+
+            let patch = server.requests().into_iter()
+                .filter(|r| r.method == "PATCH")
+                .last()
+                .unwrap();
+            let patch: serde_json::Value =
+                serde_json::from_slice(&patch.body).unwrap();
+            body = String::from(patch["body"].as_str().unwrap());
+            bodies.push(body.clone());
+        }
+        assert_eq!(bodies[0], bodies[1]);
+        assert_eq!(bodies[0].matches("* Bug fixes").count(), 1);
+
+        // The stored release has the same properties as the put: it's
+        // reported as up to date.
+        let stored = release_with_body(&server, 7, "3.8.0", &body);
+        server.on("GET", &format!("{}/7", RELEASES), 200, &stored);
+        let record = backend.get_version("3.8.0").unwrap().unwrap();
+        assert_eq!(record.desc.as_ref().map(String::as_str),
+                   Some("RabbitMQ 3.8.0"));
+        assert_eq!(record.github_release_notes_file, Some(notes));
+    }
+
+    #[test]
+    fn release_bodies_without_notes_are_descriptions() {
+        assert_eq!(split_release_body("Edited on GitHub"),
+                   (Some(String::from("Edited on GitHub")), None));
+        assert_eq!(split_release_body(
+                "<!-- github_release_notes_file: NOTES.md -->\nNotes"),
+                   (None, Some(String::from("NOTES.md"))));
+    }
+
+    #[test]
+    fn releases_are_listed_page_by_page() {
+        let server = TestServer::start();
+        let first_page = (0..PER_PAGE as u64)
+            .map(|i| release(&server, i, &format!("3.7.{}", i), false, ""))
+            .collect::<Vec<String>>()
+            .join(",");
+        server.on("GET", &format!("{}?per_page=100&page=1", RELEASES), 200,
+                  &format!("[{}]", first_page));
+        server.on("GET", &format!("{}?per_page=100&page=2", RELEASES), 200,
+                  &format!("[{}]", release(&server, 100, "3.8.0", true, "")));
+
+        let versions = backend(&server).list_versions(None).unwrap();
+        assert_eq!(versions.len(), PER_PAGE + 1);
+        assert_eq!(versions[PER_PAGE].version, "3.8.0");
+        assert_eq!(server.uris("GET").len(), 2);
+    }
+
+    #[test]
+    fn releases_are_created_as_drafts_and_published() {
+        let server = TestServer::start();
+        server.on("POST", RELEASES, 201,
+                  &release(&server, 7, "3.8.0", true, ""));
+        server.on("GET", &format!("{}/7", RELEASES), 200,
+                  &release(&server, 7, "3.8.0", true, ""));
+        server.on("PATCH", &format!("{}/7", RELEASES), 200, "{}");
+
+        let backend = backend(&server);
+        let mut record = VersionRecord::new("3.8.0");
+        record.vcs_tag = Some(String::from("v3.8.0"));
+        backend.create_version(&record).unwrap();
+        backend.publish("3.8.0").unwrap();
+
+        let requests = server.requests();
+        let created: serde_json::Value =
+            serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(created["tag_name"].as_str(), Some("v3.8.0"));
+        assert_eq!(created["name"].as_str(), Some("3.8.0"));
+        assert_eq!(created["draft"].as_bool(), Some(true));
+
+        // The release created is then looked up by id, without listing.
+        assert_eq!(server.uris("GET"), vec![format!("{}/7", RELEASES)]);
+        let published = requests.iter().find(|r| r.method == "PATCH").unwrap();
+        let published: serde_json::Value =
+            serde_json::from_slice(&published.body).unwrap();
+        assert_eq!(published["draft"].as_bool(), Some(false));
+    }
+
+    #[test]
+    fn uploads_replace_existing_assets_with_override() {
+        let server = TestServer::start();
+        let asset = r#"{"id": 12, "name": "file.txt", "label": "dir/file.txt",
+                        "size": 3, "state": "uploaded"}"#;
+        server.on("GET", RELEASES, 200,
+                  &format!("[{}]", release(&server, 7, "3.8.0", true, asset)));
+        server.on("GET", &format!("{}/7", RELEASES), 200,
+                  &release(&server, 7, "3.8.0", true, asset));
+        server.on("DELETE", &format!("{}/assets/12", RELEASES), 204, "");
+        server.on("POST", "/uploads/7/assets", 201, "{}");
+
+        let backend = backend(&server);
+        let filename = env!("CARGO_MANIFEST_DIR").to_string() + "/Cargo.toml";
+        let file = FileRecord::new("/dir/file.txt");
+        assert_eq!(error_kind(backend.upload_file("3.8.0",
+                                                  Path::new(&filename),
+                                                  &file,
+                                                  &options(false))),
+                   io::ErrorKind::AlreadyExists);
+        backend.upload_file("3.8.0", Path::new(&filename), &file,
+                            &options(true))
+            .unwrap();
+
+        assert_eq!(server.uris("DELETE"),
+                   vec![format!("{}/assets/12", RELEASES)]);
+        assert_eq!(server.uris("POST"),
+                   vec!["/uploads/7/assets?name=file.txt&\
+                         label=dir%2Ffile.txt"]);
+        // Releases are listed once, then looked up by id.
+        assert_eq!(server.uris("GET"),
+                   vec![format!("{}?per_page=100&page=1", RELEASES),
+                        format!("{}/7", RELEASES)]);
+    }
+
+    #[test]
+    fn downloads_follow_redirects_without_the_token() {
+        let server = TestServer::start();
+        let asset = r#"{"id": 12, "name": "file.txt", "label": null,
+                        "size": 3, "state": "uploaded"}"#;
+        server.on("GET", RELEASES, 200,
+                  &format!("[{}]",
+                           release(&server, 7, "3.8.0", false, asset)));
+        let location = format!("{}/storage/file.txt?signature=s", server.url);
+        server.add("GET", &format!("{}/assets/12", RELEASES), 302,
+                   &[("location", &location)], b"", false);
+        server.on("GET", "/storage/file.txt", 200, "abc");
+
+        let dir = env::temp_dir().join(
+            format!("bintray-package-github-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("file.txt");
+        backend(&server)
+            .download_file("3.8.0", &FileRecord::new("file.txt"), &filename)
+            .unwrap();
+        let mut content = String::new();
+        File::open(&filename).unwrap().read_to_string(&mut content).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(content, "abc");
+
+        let requests = server.requests();
+        let asset_request = &requests[requests.len() - 2];
+        let storage_request = &requests[requests.len() - 1];
+        assert_eq!(asset_request.header("authorization"),
+                   Some("Bearer token"));
+        assert_eq!(asset_request.header("accept"),
+                   Some("application/octet-stream"));
+        assert_eq!(storage_request.uri, "/storage/file.txt?signature=s");
+        assert_eq!(storage_request.header("authorization"), None);
+    }
+
+    #[test]
+    fn rate_limits_are_transient() {
+        let server = TestServer::start();
+        server.add("GET", "/repos/rabbitmq/server", 403,
+                   &[("x-ratelimit-remaining", "0")],
+                   br#"{"message": "API rate limit exceeded"}"#, false);
+        assert_eq!(error_kind(backend(&server).repository_exists()),
+                   io::ErrorKind::Other);
+
+        server.on("GET", "/repos/rabbitmq/server", 403,
+                  r#"{"message": "Resource not accessible"}"#);
+        assert_eq!(error_kind(backend(&server).repository_exists()),
+                   io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn duplicates_are_reported_as_already_existing() {
+        let server = TestServer::start();
+        server.on("POST", RELEASES, 422,
+                  r#"{"message": "Validation Failed",
+                      "errors": [{"resource": "Release",
+                                  "code": "already_exists",
+                                  "field": "tag_name"}]}"#);
+        let record = VersionRecord::new("3.8.0");
+        assert_eq!(error_kind(backend(&server).create_version(&record)),
+                   io::ErrorKind::AlreadyExists);
+    }
+}
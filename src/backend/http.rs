@@ -0,0 +1,64 @@
+//! Helpers shared by the backends talking to an HTTP API.
+
+use bintray::client::BintrayError;
+use hyper;
+use hyper::client::{Client, RedirectPolicy};
+use hyper::net::HttpsConnector;
+use hyper::status::StatusCode;
+use hyper_native_tls::NativeTlsClient;
+use std::io;
+
+/// Returns an HTTP(S) client which doesn't follow redirects: they are
+/// followed explicitly when needed, without the credentials.
+pub(crate) fn new_client() -> Result<Client, BintrayError> {
+    let tls = NativeTlsClient::new()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let mut client = Client::with_connector(HttpsConnector::new(tls));
+    client.set_redirect_policy(RedirectPolicy::FollowNone);
+    Ok(client)
+}
+
+/// Maps an HTTP error status to the I/O error kind the Bintray client
+/// would use, so the retry policy can tell transient errors apart.
+pub(crate) fn error_kind(status: StatusCode) -> io::ErrorKind {
+    match status {
+        StatusCode::BadRequest |
+        StatusCode::UnprocessableEntity => io::ErrorKind::InvalidInput,
+        StatusCode::Unauthorized |
+        StatusCode::Forbidden           => io::ErrorKind::PermissionDenied,
+        StatusCode::NotFound            => io::ErrorKind::NotFound,
+        StatusCode::Conflict |
+        StatusCode::PreconditionFailed  => io::ErrorKind::AlreadyExists,
+        _                               => io::ErrorKind::Other,
+    }
+}
+
+/// Builds the error for a response with an error status. `message` is
+/// the error message found in the response body, if any.
+pub(crate) fn status_error(status: StatusCode,
+                           what: &str,
+                           message: Option<String>)
+    -> BintrayError
+{
+    let message = match message {
+        Some(message) => format!("{}: {}: {}", what, status, message),
+        None          => format!("{}: {}", what, status),
+    };
+    BintrayError::from(io::Error::new(error_kind(status), message))
+}
+
+/// Connection errors, timeouts... are considered transient. The kind of
+/// errors raised while connecting is kept: the request wasn't sent, so
+/// even a non-idempotent one can be retried.
+pub(crate) fn request_error(error: hyper::Error, what: &str) -> BintrayError {
+    let kind = match error {
+        hyper::Error::Io(ref e) => match e.kind() {
+            io::ErrorKind::ConnectionRefused |
+            io::ErrorKind::NotConnected |
+            io::ErrorKind::AddrNotAvailable => e.kind(),
+            _ => io::ErrorKind::Other,
+        },
+        _ => io::ErrorKind::Other,
+    };
+    BintrayError::from(io::Error::new(kind, format!("{}: {}", what, error)))
+}
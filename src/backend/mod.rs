@@ -0,0 +1,347 @@
+//! Storage backends used by the package resource.
+//!
+//! The resource only talks to an artifact repository through the
+//! `Backend` trait. Errors are reported as `BintrayError` so all backends
+//! share the same error handling and retry policy: HTTP client errors
+//! should be mapped to I/O errors of the matching kind (`NotFound`,
+//! `PermissionDenied`...), as the Bintray client does.
+
+use bintray::client::BintrayError;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+pub mod artifactory;
+pub mod bintray;
+pub mod filesystem;
+pub mod github;
+mod http;
+pub mod s3;
+#[cfg(test)]
+mod test_server;
+
+/// Properties of a version, independent of the backend.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct VersionRecord {
+    pub version: String,
+    pub desc: Option<String>,
+    pub released: Option<String>,
+    pub vcs_tag: Option<String>,
+    pub github_release_notes_file: Option<String>,
+    pub github_use_tag_release_notes: Option<bool>,
+    pub created: Option<String>,
+    pub updated: Option<String>,
+}
+
+impl VersionRecord {
+    pub fn new(version: &str) -> VersionRecord {
+        VersionRecord {
+            version: String::from(version),
+            ..VersionRecord::default()
+        }
+    }
+}
+
+/// A file which is part of a version. `path` is relative to the root of
+/// the repository.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub size: Option<u64>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl FileRecord {
+    pub fn new<P: AsRef<Path>>(path: P) -> FileRecord {
+        FileRecord {
+            path: PathBuf::from(path.as_ref()),
+            ..FileRecord::default()
+        }
+    }
+}
+
+/// Version properties stored as a JSON document by backends without a
+/// version record of their own.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct VersionMetadata {
+    desc: Option<String>,
+    released: Option<String>,
+    vcs_tag: Option<String>,
+    github_release_notes_file: Option<String>,
+    github_use_tag_release_notes: Option<bool>,
+    created: Option<String>,
+    updated: Option<String>,
+    /// Files uploaded but not published yet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unpublished: Vec<PathBuf>,
+    /// Checksums of the uploaded files, by path, for backends which can't
+    /// list them with the files.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, StoredChecksums>,
+}
+
+/// Checksums of an uploaded file. They are only valid while the stored
+/// file has the same `etag`: another tool may have replaced it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct StoredChecksums {
+    pub etag: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+impl VersionMetadata {
+    /// Metadata of a new version, created now.
+    pub fn new(record: &VersionRecord) -> VersionMetadata {
+        let mut metadata = VersionMetadata::default();
+        metadata.update(record);
+        metadata.created = metadata.updated.clone();
+        metadata
+    }
+
+    /// Copies the version properties from `record` and bumps the update
+    /// date.
+    pub fn update(&mut self, record: &VersionRecord) {
+        self.desc = record.desc.clone();
+        self.released = record.released.clone();
+        self.vcs_tag = record.vcs_tag.clone();
+        self.github_release_notes_file =
+            record.github_release_notes_file.clone();
+        self.github_use_tag_release_notes =
+            record.github_use_tag_release_notes;
+        self.updated = Some(now());
+    }
+
+    pub fn into_record(self, version: &str) -> VersionRecord {
+        VersionRecord {
+            version: String::from(version),
+            desc: self.desc,
+            released: self.released,
+            vcs_tag: self.vcs_tag,
+            github_release_notes_file: self.github_release_notes_file,
+            github_use_tag_release_notes: self.github_use_tag_release_notes,
+            created: self.created,
+            updated: self.updated,
+        }
+    }
+}
+
+/// Returns the current date formatted like Bintray dates. All dates are
+/// formatted the same way so they sort chronologically as strings.
+pub(crate) fn now() -> String {
+    Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+/// Sorts versions from the oldest to the most recent and drops those
+/// before `since`, if it's set and found.
+pub(crate) fn versions_since(mut versions: Vec<VersionRecord>,
+                             since: Option<&str>)
+    -> Vec<VersionRecord>
+{
+    versions.sort_by(|a, b| {
+        a.created.cmp(&b.created).then_with(|| a.version.cmp(&b.version))
+    });
+
+    match since.and_then(|since| {
+        versions.iter().position(|v| v.version == since)
+    }) {
+        Some(pos) => versions.split_off(pos),
+        None      => versions,
+    }
+}
+
+/// Drops root and `..` components so a remote path can't point outside
+/// of the version.
+pub fn relative_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c),
+            _                    => None,
+        })
+        .collect()
+}
+
+/// Fails if `version` isn't a single path component. Backends name the
+/// directory, key prefix or folder of a version after it: it must not
+/// point outside of the package.
+pub(crate) fn check_version(version: &str) -> Result<(), BintrayError> {
+    if version.is_empty() || version == "." || version == ".." ||
+        version.contains(|c| c == '/' || c == '\\') {
+        return Err(BintrayError::from(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid version \"{}\": it can't be empty, \
+                             `.`, `..` or contain a path separator",
+                            version))));
+    }
+    Ok(())
+}
+
+/// Package properties from the `package_props` params. `None` means the
+/// property is left unchanged. Backends without a package record ignore
+/// them.
+#[derive(Clone, Debug, Default)]
+pub struct PackageProps {
+    pub desc: Option<String>,
+    pub labels: Option<Vec<String>>,
+    pub public_download_numbers: Option<bool>,
+    pub public_stats: Option<bool>,
+    pub maturity: Option<String>,
+    pub licenses: Option<Vec<String>>,
+    pub custom_licenses: Option<Vec<String>>,
+    pub website_url: Option<String>,
+    pub issue_tracker_url: Option<String>,
+    pub vcs_url: Option<String>,
+    pub github_repo: Option<String>,
+    pub github_release_notes_file: Option<String>,
+}
+
+/// What creating or updating a record did, or would do in a dry run.
+/// Changes are `field: old -> new` strings.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordChange {
+    Created(Vec<String>),
+    Updated(Vec<String>),
+    UpToDate,
+}
+
+pub struct UploadOptions<'a> {
+    pub publish: bool,
+    pub override_: bool,
+    pub debian_architecture: &'a [String],
+    pub debian_distribution: &'a [String],
+    pub debian_component: &'a [String],
+}
+
+/// Operations on one package of an artifact repository.
+///
+/// Methods returning an `Option<String>` return a warning message from
+/// the backend, if any.
+pub trait Backend {
+    /// Tells if the repository the package belongs to exists.
+    fn repository_exists(&self) -> Result<bool, BintrayError>;
+
+    fn package_exists(&self) -> Result<bool, BintrayError>;
+
+    /// Creates the package record if it's missing or updates it with
+    /// `props`. Nothing is changed if `dry_run` is true.
+    fn update_package(&self, props: &PackageProps, dry_run: bool)
+        -> Result<RecordChange, BintrayError>;
+
+    fn delete_package(&self) -> Result<Option<String>, BintrayError>;
+
+    /// Lists versions from the oldest to the most recent, starting at
+    /// `since` if it's set. Returns a `NotFound` error if the package
+    /// doesn't exist.
+    fn list_versions(&self, since: Option<&str>)
+        -> Result<Vec<VersionRecord>, BintrayError>;
+
+    /// Lists the names of the versions from the oldest to the most recent,
+    /// for callers which don't need the records. Backends which can list
+    /// them without reading each record should override it.
+    fn list_version_names(&self) -> Result<Vec<String>, BintrayError> {
+        Ok(self.list_versions(None)?
+           .into_iter()
+           .map(|v| v.version)
+           .collect())
+    }
+
+    /// Returns `None` if the version doesn't exist.
+    fn get_version(&self, version: &str)
+        -> Result<Option<VersionRecord>, BintrayError>;
+
+    fn create_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>;
+
+    fn update_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>;
+
+    fn delete_version(&self, version: &str)
+        -> Result<Option<String>, BintrayError>;
+
+    fn list_files(&self, version: &str, include_unpublished: bool)
+        -> Result<Vec<FileRecord>, BintrayError>;
+
+    /// Tells if the version has files which are not published yet. By
+    /// default, the files are listed with and without unpublished ones;
+    /// backends which can tell from one listing should override it.
+    fn has_unpublished_files(&self, version: &str)
+        -> Result<bool, BintrayError>
+    {
+        let all_files = self.list_files(version, true)?;
+        let published_files = self.list_files(version, false)?;
+        Ok(all_files.len() > published_files.len())
+    }
+
+    /// Uploads the local file `filename` as `file`.
+    fn upload_file(&self,
+                   version: &str,
+                   filename: &Path,
+                   file: &FileRecord,
+                   options: &UploadOptions)
+        -> Result<Option<String>, BintrayError>;
+
+    /// Downloads `file` to the local file `filename`.
+    fn download_file(&self, version: &str, file: &FileRecord, filename: &Path)
+        -> Result<(), BintrayError>;
+
+    fn remove_file(&self, version: &str, file: &FileRecord)
+        -> Result<Option<String>, BintrayError>;
+
+    /// Called once the files of the version are uploaded and removed,
+    /// before publishing. Backends which keep data about each file in an
+    /// object of the version write it once here rather than on each
+    /// upload.
+    fn finish_uploads(&self, _version: &str) -> Result<(), BintrayError> {
+        Ok(())
+    }
+
+    /// Publishes the uploaded files of the version. Returns the number of
+    /// files which are not published yet: the caller is expected to call
+    /// this again until it returns 0.
+    fn publish(&self, version: &str) -> Result<usize, BintrayError>;
+
+    /// Lists the file in the download list of the package, if the backend
+    /// has one. Returns a `NotFound` error if the file is not published
+    /// yet.
+    fn show_in_download_list(&self, version: &str, file: &FileRecord)
+        -> Result<(), BintrayError>;
+
+    /// Tells if uploading a file again after an upload which may have
+    /// been applied gives the same result. Without `override_`, the
+    /// second upload finds the file and fails.
+    fn is_upload_idempotent(&self, options: &UploadOptions) -> bool {
+        options.override_
+    }
+
+    /// Returns true if the backend builds the APT and YUM indexes of the
+    /// repository itself. Otherwise, they are generated locally and
+    /// uploaded along with the packages.
+    fn builds_indexes(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_single_path_components() {
+        assert!(check_version("3.8.0").is_ok());
+        assert!(check_version("3.8.0-rc.1+build..2").is_ok());
+        assert!(check_version("..3").is_ok());
+
+        for version in &["", ".", "..", "../3.8.0", "3.8/../..", "a/b",
+                         "/tmp", "a\\b"] {
+            assert!(check_version(version).is_err(), "{}", version);
+        }
+    }
+
+    #[test]
+    fn relative_paths_stay_inside_the_version() {
+        assert_eq!(relative_path(Path::new("/a/../../b/c")),
+                   PathBuf::from("a/b/c"));
+        assert_eq!(relative_path(Path::new("./a/b")), PathBuf::from("a/b"));
+    }
+}
@@ -0,0 +1,925 @@
+use backend::{Backend, FileRecord, PackageProps, RecordChange, StoredChecksums,
+              UploadOptions, VersionMetadata, VersionRecord, check_version,
+              http, relative_path, versions_since};
+use bintray::client::BintrayError;
+use checksum;
+use chrono::Utc;
+use hyper::client::{Body, Client, Response};
+use hyper::header::Headers;
+use hyper::method::Method;
+use hyper::Url;
+use regex::Regex;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Key of the JSON object holding the version properties, relative to the
+/// version prefix.
+const METADATA_KEY: &'static str = ".version.json";
+
+/// Prefix of the empty objects marking unpublished files, relative to
+/// the version prefix.
+const UNPUBLISHED_PREFIX: &'static str = ".unpublished/";
+
+/// Checksums of the files uploaded or removed by this process, by
+/// endpoint, bucket and key, until `finish_uploads()` writes them to the
+/// metadata object of their version. Uploads run in parallel with a
+/// backend per thread. `None` drops the checksums of a removed file.
+static PENDING_CHECKSUMS: Mutex<BTreeMap<String, Option<StoredChecksums>>> =
+    Mutex::new(BTreeMap::new());
+
+/// SHA-256 of an empty payload.
+const EMPTY_SHA256: &'static str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// A package stored in an S3-compatible bucket:
+///
+/// ```text
+/// <subject>/<repository>/<package>/<version>/<files...>
+/// <subject>/<repository>/<package>/<version>/.version.json
+/// <subject>/<repository>/<package>/<version>/.unpublished/<files...>
+/// ```
+///
+/// Versions are the prefixes below the package prefix. Unpublished files
+/// are tracked with empty marker objects rather than in the JSON object,
+/// so publishing doesn't have to update it. The JSON object holds the
+/// checksums of the uploaded files, written once per version after the
+/// uploads: listing objects only returns their ETag, which isn't a SHA-1
+/// or SHA-256. Requests use path-style URLs and AWS Signature Version 4,
+/// which S3 and MinIO both support.
+pub struct S3Backend {
+    client: Client,
+    endpoint: Url,
+    region: String,
+    bucket: String,
+    credentials: S3Credentials,
+    package_prefix: String,
+}
+
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+enum Payload<'a> {
+    Empty,
+    Bytes(&'a [u8]),
+    File(&'a mut File, u64, String),
+}
+
+struct S3Object {
+    key: String,
+    size: u64,
+    etag: String,
+}
+
+impl S3Backend {
+    /// Without an `endpoint`, the AWS endpoint of the region is used.
+    pub fn new(endpoint: Option<&str>,
+               region: &str,
+               bucket: &str,
+               credentials: S3Credentials,
+               subject: &str,
+               repository: &str,
+               package: &str)
+        -> Result<S3Backend, BintrayError>
+    {
+        let endpoint = match endpoint {
+            Some(endpoint) => String::from(endpoint),
+            None => format!("https://s3.{}.amazonaws.com", region),
+        };
+        let endpoint = Url::parse(&endpoint)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput,
+                                        format!("{}: {}", endpoint, e)))?;
+
+        Ok(S3Backend {
+            client: http::new_client()?,
+            endpoint: endpoint,
+            region: String::from(region),
+            bucket: String::from(bucket),
+            credentials: credentials,
+            package_prefix: format!("{}/{}/{}/",
+                                    subject, repository, package),
+        })
+    }
+
+    /// Fails if the version would point outside of the package prefix.
+    fn version_prefix(&self, version: &str) -> Result<String, BintrayError> {
+        check_version(version)?;
+        Ok(format!("{}{}/", self.package_prefix, version))
+    }
+
+    fn file_key(&self, version: &str, path: &Path)
+        -> Result<String, BintrayError>
+    {
+        Ok(format!("{}{}",
+                   self.version_prefix(version)?,
+                   relative_path(path).to_string_lossy()))
+    }
+
+    fn marker_key(&self, version: &str, path: &Path)
+        -> Result<String, BintrayError>
+    {
+        Ok(format!("{}{}{}",
+                   self.version_prefix(version)?,
+                   UNPUBLISHED_PREFIX,
+                   relative_path(path).to_string_lossy()))
+    }
+
+    /// Sends a signed request for `key` (or the bucket itself) and
+    /// returns the response if its status is a success.
+    fn request(&self,
+               method: Method,
+               key: Option<&str>,
+               query: &[(&str, &str)],
+               meta: &[(&str, &str)],
+               payload: Payload)
+        -> Result<Response, BintrayError>
+    {
+        let mut path = format!("/{}", uri_encode(&self.bucket, true));
+        match key {
+            Some(key) => {
+                path.push('/');
+                path.push_str(&uri_encode(key, false));
+            }
+            None => { }
+        }
+
+        let mut query = query.iter()
+            .map(|&(k, v)| format!("{}={}",
+                                   uri_encode(k, true), uri_encode(v, true)))
+            .collect::<Vec<String>>();
+        query.sort();
+        let query = query.join("&");
+
+        let host = match (self.endpoint.host_str(), self.endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None)       => String::from(host),
+            (None, _)                => String::new(),
+        };
+        let payload_hash = match payload {
+            Payload::Empty                  => String::from(EMPTY_SHA256),
+            Payload::Bytes(bytes)           => {
+                checksum::to_hex(&checksum::sha256(bytes))
+            }
+            Payload::File(_, _, ref sha256) => sha256.clone(),
+        };
+
+        let mut headers = vec![
+            (String::from("host"), host),
+            (String::from("x-amz-content-sha256"), payload_hash.clone()),
+            (String::from("x-amz-date"),
+             Utc::now().format("%Y%m%dT%H%M%SZ").to_string()),
+        ];
+        for &(name, value) in meta {
+            headers.push((format!("x-amz-meta-{}", name), String::from(value)));
+        }
+        let authorization = self.authorization(&method.to_string(),
+                                               &path,
+                                               &query,
+                                               &mut headers,
+                                               &payload_hash);
+
+        let mut hyper_headers = Headers::new();
+        for (name, value) in headers {
+            hyper_headers.set_raw(name, vec![value.into_bytes()]);
+        }
+        hyper_headers.set_raw("authorization",
+                              vec![authorization.into_bytes()]);
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        url.set_query(if query.is_empty() { None } else { Some(&query) });
+
+        let what = format!("{} {}", method, url);
+        let builder = self.client.request(method, url).headers(hyper_headers);
+        let response = match payload {
+            Payload::Empty => builder.send(),
+            Payload::Bytes(bytes) => {
+                builder.body(Body::BufBody(bytes, bytes.len())).send()
+            }
+            Payload::File(file, len, _) => {
+                builder.body(Body::SizedBody(file, len)).send()
+            }
+        };
+        let response = response.map_err(|e| http::request_error(e, &what))?;
+        check_status(response, &what)
+    }
+
+    /// Computes the `Authorization` header of a request, as described in
+    /// https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html
+    fn authorization(&self,
+                     method: &str,
+                     path: &str,
+                     query: &str,
+                     headers: &mut Vec<(String, String)>,
+                     payload_hash: &str)
+        -> String
+    {
+        headers.sort();
+        let canonical_headers = headers.iter()
+            .map(|&(ref name, ref value)| {
+                format!("{}:{}\n", name, value.trim())
+            })
+            .collect::<String>();
+        let signed_headers = headers.iter()
+            .map(|&(ref name, _)| name.as_str())
+            .collect::<Vec<&str>>()
+            .join(";");
+        let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}",
+                                        method,
+                                        path,
+                                        query,
+                                        canonical_headers,
+                                        signed_headers,
+                                        payload_hash);
+
+        let timestamp = headers.iter()
+            .find(|&&(ref name, _)| name == "x-amz-date")
+            .map(|&(_, ref value)| value.clone())
+            .unwrap_or_default();
+        let date = &timestamp[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            checksum::to_hex(&checksum::sha256(canonical_request.as_bytes())));
+
+        let key = format!("AWS4{}", self.credentials.secret_access_key);
+        let key = checksum::hmac_sha256(key.as_bytes(), date.as_bytes());
+        let key = checksum::hmac_sha256(&key, self.region.as_bytes());
+        let key = checksum::hmac_sha256(&key, b"s3");
+        let key = checksum::hmac_sha256(&key, b"aws4_request");
+        let signature = checksum::hmac_sha256(&key,
+                                              string_to_sign.as_bytes());
+
+        format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, \
+                 Signature={}",
+                self.credentials.access_key_id,
+                scope,
+                signed_headers,
+                checksum::to_hex(&signature))
+    }
+
+    /// Lists objects below `prefix`. With `delimiter`, objects in
+    /// "subdirectories" are grouped in common prefixes, returned as the
+    /// second member of the tuple.
+    fn list_objects(&self, prefix: &str, delimiter: bool)
+        -> Result<(Vec<S3Object>, Vec<String>), BintrayError>
+    {
+        let contents_re = Regex::new(r"(?s)<Contents>(.*?)</Contents>")
+            .unwrap();
+        let key_re = Regex::new(r"<Key>(.*?)</Key>").unwrap();
+        let size_re = Regex::new(r"<Size>(\d+)</Size>").unwrap();
+        let etag_re = Regex::new(r"<ETag>(.*?)</ETag>").unwrap();
+        let prefix_re = Regex::new(
+            r"(?s)<CommonPrefixes>\s*<Prefix>(.*?)</Prefix>").unwrap();
+        let token_re = Regex::new(
+            r"<NextContinuationToken>(.*?)</NextContinuationToken>").unwrap();
+
+        let mut objects = vec![];
+        let mut prefixes = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if delimiter {
+                query.push(("delimiter", "/"));
+            }
+            match token {
+                Some(ref token) => query.push(("continuation-token", token)),
+                None            => { }
+            }
+
+            let mut body = String::new();
+            self.request(Method::Get, None, &query, &[], Payload::Empty)?
+                .read_to_string(&mut body)?;
+
+            for contents in contents_re.captures_iter(&body) {
+                let contents = &contents[1];
+                let key = key_re.captures(contents)
+                    .map(|c| xml_unescape(&c[1]));
+                let size = size_re.captures(contents)
+                    .and_then(|c| c[1].parse::<u64>().ok());
+                let etag = etag_re.captures(contents)
+                    .map(|c| xml_unescape(&c[1]))
+                    .unwrap_or_default();
+                match (key, size) {
+                    (Some(key), Some(size)) => {
+                        objects.push(S3Object {
+                            key: key,
+                            size: size,
+                            etag: etag,
+                        });
+                    }
+                    _ => { }
+                }
+            }
+            for prefix in prefix_re.captures_iter(&body) {
+                prefixes.push(xml_unescape(&prefix[1]));
+            }
+
+            token = if body.contains("<IsTruncated>true</IsTruncated>") {
+                token_re.captures(&body).map(|c| xml_unescape(&c[1]))
+            } else {
+                None
+            };
+            if token.is_none() {
+                return Ok((objects, prefixes));
+            }
+        }
+    }
+
+    fn delete_objects(&self, prefix: &str) -> Result<(), BintrayError> {
+        let (objects, _) = self.list_objects(prefix, false)?;
+        for object in objects {
+            self.delete_object(&object.key)?;
+        }
+        Ok(())
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), BintrayError> {
+        self.request(Method::Delete, Some(key), &[], &[], Payload::Empty)?;
+        Ok(())
+    }
+
+    /// Returns `None` if the object doesn't exist.
+    fn head_object(&self, key: &str)
+        -> Result<Option<Response>, BintrayError>
+    {
+        match self.request(Method::Head, Some(key), &[], &[], Payload::Empty) {
+            Ok(response) => Ok(Some(response)),
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns `None` if the version doesn't exist. A version prefix
+    /// without a metadata object (e.g. populated by another tool) is a
+    /// version without properties.
+    fn read_metadata(&self, version: &str)
+        -> Result<Option<VersionMetadata>, BintrayError>
+    {
+        let prefix = self.version_prefix(version)?;
+        let key = format!("{}{}", prefix, METADATA_KEY);
+        match self.request(Method::Get, Some(&key), &[], &[], Payload::Empty) {
+            Ok(response) => {
+                let metadata = serde_json::from_reader(response)
+                    .map_err(BintrayError::Json)?;
+                Ok(Some(metadata))
+            }
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => {
+                let (objects, _) = self.list_objects(&prefix, false)?;
+                if objects.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(VersionMetadata::default()))
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_metadata(&self, version: &str, metadata: &VersionMetadata)
+        -> Result<(), BintrayError>
+    {
+        let key = format!("{}{}", self.version_prefix(version)?, METADATA_KEY);
+        let json = serde_json::to_string_pretty(metadata)
+            .map_err(BintrayError::Json)?;
+        self.request(Method::Put, Some(&key), &[], &[],
+                     Payload::Bytes(json.as_bytes()))?;
+        Ok(())
+    }
+
+    /// Records the checksums of `key` until `finish_uploads()`.
+    fn set_pending_checksums(&self,
+                             key: &str,
+                             checksums: Option<StoredChecksums>)
+    {
+        pending_checksums().insert(self.pending_key(key), checksums);
+    }
+
+    fn pending_key(&self, key: &str) -> String {
+        format!("{}{}/{}", self.endpoint, self.bucket, key)
+    }
+}
+
+fn pending_checksums()
+    -> ::std::sync::MutexGuard<'static,
+                               BTreeMap<String, Option<StoredChecksums>>>
+{
+    // A thread panicking while holding the lock doesn't make the map
+    // invalid.
+    PENDING_CHECKSUMS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn check_status(mut response: Response, what: &str)
+    -> Result<Response, BintrayError>
+{
+    if response.status.is_success() {
+        return Ok(response);
+    }
+
+    let mut body = String::new();
+    let _ = response.read_to_string(&mut body);
+    let message = Regex::new(r"<Message>(.*?)</Message>").unwrap()
+        .captures(&body)
+        .map(|c| xml_unescape(&c[1]));
+    Err(http::status_error(response.status, what, message))
+}
+
+/// Percent-encodes everything but unreserved characters (and slashes,
+/// unless `encode_slash` is set), as required by the signature.
+fn uri_encode(input: &str, encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' |
+            b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash     => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decodes the predefined entities and the character references of XML
+/// text, in a single pass so `&amp;lt;` gives `&lt;`. Unknown entities
+/// are kept as is.
+fn xml_unescape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "lt"   => Some('<'),
+                "gt"   => Some('>'),
+                "amp"  => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                entity if entity.starts_with("#x") => {
+                    u32::from_str_radix(&entity[2..], 16).ok()
+                        .and_then(std::char::from_u32)
+                }
+                entity if entity.starts_with('#') => {
+                    entity[1..].parse::<u32>().ok()
+                        .and_then(std::char::from_u32)
+                }
+                _ => None,
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                output.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// ETags are quoted, but some servers drop the quotes.
+fn same_etag(a: &str, b: &str) -> bool {
+    a.trim_matches('"') == b.trim_matches('"')
+}
+
+fn not_found(version: &str) -> BintrayError {
+    BintrayError::from(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Version {} not found", version)))
+}
+
+fn header_value(response: &Response, name: &str) -> Option<String> {
+    response.headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+}
+
+impl Backend for S3Backend {
+    fn repository_exists(&self) -> Result<bool, BintrayError> {
+        // Repositories are just prefixes: only the bucket has to exist.
+        match self.request(Method::Head, None, &[], &[], Payload::Empty) {
+            Ok(_) => Ok(true),
+            Err(BintrayError::Io(ref e))
+                if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn package_exists(&self) -> Result<bool, BintrayError> {
+        let (objects, prefixes) = self.list_objects(&self.package_prefix,
+                                                    true)?;
+        Ok(!objects.is_empty() || !prefixes.is_empty())
+    }
+
+    fn update_package(&self, _props: &PackageProps, _dry_run: bool)
+        -> Result<RecordChange, BintrayError>
+    {
+        // There is no package record: the package prefix appears with
+        // the first version.
+        if self.package_exists()? {
+            Ok(RecordChange::UpToDate)
+        } else {
+            Ok(RecordChange::Created(vec![]))
+        }
+    }
+
+    fn delete_package(&self) -> Result<Option<String>, BintrayError> {
+        self.delete_objects(&self.package_prefix)?;
+        Ok(None)
+    }
+
+    fn list_versions(&self, since: Option<&str>)
+        -> Result<Vec<VersionRecord>, BintrayError>
+    {
+        let (_, prefixes) = self.list_objects(&self.package_prefix, true)?;
+        if prefixes.is_empty() {
+            return Err(BintrayError::from(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Package {} not found",
+                                self.package_prefix))));
+        }
+
+        let mut versions = vec![];
+        for prefix in prefixes {
+            let version = prefix[self.package_prefix.len()..]
+                .trim_end_matches('/')
+                .to_string();
+            match self.read_metadata(&version)? {
+                Some(metadata) => {
+                    versions.push(metadata.into_record(&version));
+                }
+                None => { }
+            }
+        }
+
+        Ok(versions_since(versions, since))
+    }
+
+    fn get_version(&self, version: &str)
+        -> Result<Option<VersionRecord>, BintrayError>
+    {
+        Ok(self.read_metadata(version)?
+           .map(|metadata| metadata.into_record(version)))
+    }
+
+    fn create_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        self.write_metadata(&record.version, &VersionMetadata::new(record))
+    }
+
+    fn update_version(&self, record: &VersionRecord)
+        -> Result<(), BintrayError>
+    {
+        let mut metadata = self.read_metadata(&record.version)?
+            .ok_or_else(|| not_found(&record.version))?;
+        metadata.update(record);
+        self.write_metadata(&record.version, &metadata)
+    }
+
+    fn delete_version(&self, version: &str)
+        -> Result<Option<String>, BintrayError>
+    {
+        self.delete_objects(&self.version_prefix(version)?)?;
+        Ok(None)
+    }
+
+    fn list_files(&self, version: &str, include_unpublished: bool)
+        -> Result<Vec<FileRecord>, BintrayError>
+    {
+        let version_prefix = self.version_prefix(version)?;
+        let (objects, _) = self.list_objects(&version_prefix, false)?;
+        if objects.is_empty() {
+            return Err(not_found(version));
+        }
+        let metadata = self.read_metadata(version)?.unwrap_or_default();
+
+        let unpublished = objects.iter()
+            .filter_map(|o| {
+                let path = &o.key[version_prefix.len()..];
+                if path.starts_with(UNPUBLISHED_PREFIX) {
+                    Some(String::from(&path[UNPUBLISHED_PREFIX.len()..]))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<String>>();
+
+        let mut files = vec![];
+        for object in objects.iter() {
+            let path = &object.key[version_prefix.len()..];
+            if path == METADATA_KEY || path.starts_with(UNPUBLISHED_PREFIX) {
+                continue;
+            }
+            if !include_unpublished &&
+                unpublished.iter().any(|p| p == path) {
+                continue;
+            }
+
+            // Checksums are recorded by `upload_file()`.
+            let checksums = metadata.checksums.get(path)
+                .filter(|c| same_etag(&c.etag, &object.etag));
+            files.push(FileRecord {
+                path: PathBuf::from(path),
+                size: Some(object.size),
+                sha1: checksums.map(|c| c.sha1.clone()),
+                sha256: checksums.map(|c| c.sha256.clone()),
+            });
+        }
+        Ok(files)
+    }
+
+    fn has_unpublished_files(&self, version: &str)
+        -> Result<bool, BintrayError>
+    {
+        let version_prefix = self.version_prefix(version)?;
+        let (objects, _) = self.list_objects(&version_prefix, false)?;
+        if objects.is_empty() {
+            return Err(not_found(version));
+        }
+        Ok(objects.iter().any(|o| {
+            o.key[version_prefix.len()..].starts_with(UNPUBLISHED_PREFIX)
+        }))
+    }
+
+    fn upload_file(&self,
+                   version: &str,
+                   filename: &Path,
+                   file: &FileRecord,
+                   options: &UploadOptions)
+        -> Result<Option<String>, BintrayError>
+    {
+        let key = self.file_key(version, &file.path)?;
+        if !options.override_ && self.head_object(&key)?.is_some() {
+            return Err(BintrayError::from(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", file.path.display()))));
+        }
+
+        let sha1 = checksum::sha1_file(filename)?;
+        let sha256 = checksum::sha256_file(filename)?;
+        let mut local_file = File::open(filename)?;
+        let len = local_file.metadata()?.len();
+        let response = self.request(
+            Method::Put,
+            Some(&key),
+            &[],
+            &[("sha1", &sha1), ("sha256", &sha256)],
+            Payload::File(&mut local_file, len, sha256.clone()))?;
+
+        let checksums = header_value(&response, "etag")
+            .map(|etag| StoredChecksums {
+                etag: etag,
+                sha1: sha1,
+                sha256: sha256,
+            });
+        self.set_pending_checksums(&key, checksums);
+
+        let marker_key = self.marker_key(version, &file.path)?;
+        if options.publish {
+            self.delete_object(&marker_key)?;
+        } else {
+            self.request(Method::Put, Some(&marker_key), &[], &[],
+                         Payload::Empty)?;
+        }
+        Ok(None)
+    }
+
+    fn download_file(&self, version: &str, file: &FileRecord, filename: &Path)
+        -> Result<(), BintrayError>
+    {
+        let key = self.file_key(version, &file.path)?;
+        let mut response = self.request(Method::Get, Some(&key), &[], &[],
+                                        Payload::Empty)?;
+        let mut local_file = File::create(filename)?;
+        io::copy(&mut response, &mut local_file)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, version: &str, file: &FileRecord)
+        -> Result<Option<String>, BintrayError>
+    {
+        let key = self.file_key(version, &file.path)?;
+        self.delete_object(&key)?;
+        self.delete_object(&self.marker_key(version, &file.path)?)?;
+        self.set_pending_checksums(&key, None);
+        Ok(None)
+    }
+
+    fn finish_uploads(&self, version: &str) -> Result<(), BintrayError> {
+        let prefix = self.pending_key(&self.version_prefix(version)?);
+        let pending = pending_checksums()
+            .range(prefix.clone()..)
+            .take_while(|&(key, _)| key.starts_with(&prefix))
+            .map(|(key, checksums)| (key.clone(), checksums.clone()))
+            .collect::<Vec<(String, Option<StoredChecksums>)>>();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut metadata = self.read_metadata(version)?.unwrap_or_default();
+        for &(ref key, ref checksums) in pending.iter() {
+            let path = String::from(&key[prefix.len()..]);
+            match *checksums {
+                Some(ref checksums) => {
+                    metadata.checksums.insert(path, checksums.clone());
+                }
+                None => { metadata.checksums.remove(&path); }
+            }
+        }
+        self.write_metadata(version, &metadata)?;
+
+        // Kept until written, so a retry writes them again.
+        let mut pending_checksums = pending_checksums();
+        for (key, _) in pending {
+            pending_checksums.remove(&key);
+        }
+        Ok(())
+    }
+
+    fn publish(&self, version: &str) -> Result<usize, BintrayError> {
+        self.delete_objects(&format!("{}{}",
+                                     self.version_prefix(version)?,
+                                     UNPUBLISHED_PREFIX))?;
+        Ok(0)
+    }
+
+    fn show_in_download_list(&self, _version: &str, _file: &FileRecord)
+        -> Result<(), BintrayError>
+    {
+        // There is no download list.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::test_server::TestServer;
+
+    fn backend(server: &TestServer) -> S3Backend {
+        let credentials = S3Credentials {
+            access_key_id: String::from("key"),
+            secret_access_key: String::from("secret"),
+        };
+        S3Backend::new(Some(&server.url), "us-east-1", "bucket", credentials,
+                       "team", "generic", "server")
+            .unwrap()
+    }
+
+    fn listing(objects: &[(&str, u64, &str)]) -> String {
+        let contents = objects.iter()
+            .map(|&(key, size, etag)| {
+                format!("<Contents><Key>{}</Key><Size>{}</Size>\
+                         <ETag>{}</ETag></Contents>", key, size, etag)
+            })
+            .collect::<String>();
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                 <ListBucketResult><IsTruncated>false</IsTruncated>{}\
+                 </ListBucketResult>", contents)
+    }
+
+    #[test]
+    fn xml_entities_are_decoded_once() {
+        assert_eq!(xml_unescape("a&amp;b &lt;c&gt; &quot;d&quot; &apos;"),
+                   "a&b <c> \"d\" '");
+        assert_eq!(xml_unescape("&#38;&#x41;&#10;"), "&A\n");
+        assert_eq!(xml_unescape("&amp;lt;"), "&lt;");
+        assert_eq!(xml_unescape("a & b &unknown; &#xZZ;"),
+                   "a & b &unknown; &#xZZ;");
+    }
+
+    #[test]
+    fn list_files_reads_checksums_from_metadata() {
+        let server = TestServer::start();
+        server.on("GET", "/bucket", 200, &listing(&[
+            ("team/generic/server/3.8.0/.version.json", 100, "&quot;m&quot;"),
+            ("team/generic/server/3.8.0/a&amp;b.txt", 3, "&quot;e1&quot;"),
+            ("team/generic/server/3.8.0/replaced.txt", 5, "&quot;e3&quot;"),
+            ("team/generic/server/3.8.0/.unpublished/replaced.txt", 0,
+             "&quot;x&quot;"),
+        ]));
+        server.on("GET", "/bucket/team/generic/server/3.8.0/.version.json",
+                  200, r#"{
+                      "checksums": {
+                          "a&b.txt": {
+                              "etag": "\"e1\"", "sha1": "s1", "sha256": "s256"
+                          },
+                          "replaced.txt": {
+                              "etag": "\"e2\"", "sha1": "t1", "sha256": "t256"
+                          }
+                      }
+                  }"#);
+
+        let files = backend(&server).list_files("3.8.0", true).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, PathBuf::from("a&b.txt"));
+        assert_eq!(files[0].size, Some(3));
+        assert_eq!(files[0].sha1.as_ref().map(String::as_str), Some("s1"));
+        assert_eq!(files[0].sha256.as_ref().map(String::as_str),
+                   Some("s256"));
+        // The file was replaced since its checksums were recorded.
+        assert_eq!(files[1].path, PathBuf::from("replaced.txt"));
+        assert_eq!(files[1].sha1, None);
+
+        let published = backend(&server).list_files("3.8.0", false).unwrap();
+        assert_eq!(published.len(), 1);
+
+        // No object is fetched one by one.
+        assert!(server.uris("HEAD").is_empty());
+    }
+
+    #[test]
+    fn uploads_record_checksums_once_per_version() {
+        let server = TestServer::start();
+        let prefix = "/bucket/team/generic/server/3.8.0/";
+        server.add("PUT", &format!("{}file.txt", prefix), 200,
+                   &[("etag", "\"e1\"")], b"", false);
+        server.add("PUT", &format!("{}other.txt", prefix), 200,
+                   &[("etag", "\"e2\"")], b"", false);
+        server.on("DELETE", &format!("{}old.txt", prefix), 204, "");
+        server.on("DELETE", &format!("{}.unpublished/old.txt", prefix),
+                  204, "");
+        server.on("DELETE", &format!("{}.unpublished/other.txt", prefix),
+                  204, "");
+        server.on("PUT", &format!("{}.version.json", prefix), 200, "");
+        server.on("GET", &format!("{}.version.json", prefix), 200,
+                  r#"{
+                      "vcs_tag": "v3.8.0",
+                      "checksums": {
+                          "old.txt": {
+                              "etag": "\"e0\"", "sha1": "o1", "sha256": "o256"
+                          }
+                      }
+                  }"#);
+        server.on("DELETE", &format!("{}.unpublished/file.txt", prefix),
+                  204, "");
+
+        let filename = env!("CARGO_MANIFEST_DIR").to_string() + "/Cargo.toml";
+        let options = UploadOptions {
+            publish: true,
+            override_: true,
+            debian_architecture: &[],
+            debian_distribution: &[],
+            debian_component: &[],
+        };
+        let backend = backend(&server);
+        for path in &["/file.txt", "other.txt"] {
+            backend.upload_file("3.8.0",
+                                Path::new(&filename),
+                                &FileRecord::new(Path::new(path)),
+                                &options)
+                .unwrap();
+        }
+        backend.remove_file("3.8.0", &FileRecord::new("old.txt")).unwrap();
+        assert!(server.uris("GET").is_empty());
+
+        backend.finish_uploads("3.8.0").unwrap();
+        backend.finish_uploads("3.8.0").unwrap();
+        assert_eq!(server.uris("GET"),
+                   vec![format!("{}.version.json", prefix)]);
+
+        let upload = server.requests().into_iter()
+            .find(|r| r.method == "PUT" && r.uri.ends_with("file.txt"))
+            .unwrap();
+        assert_eq!(upload.header("x-amz-meta-sha256").map(String::from),
+                   checksum::sha256_file(Path::new(&filename)).ok());
+
+        let metadata = server.requests().into_iter()
+            .filter(|r| r.method == "PUT" && r.uri.ends_with(".version.json"))
+            .collect::<Vec<_>>();
+        assert_eq!(metadata.len(), 1);
+        let metadata = &metadata[0];
+        let metadata: serde_json::Value =
+            serde_json::from_slice(&metadata.body).unwrap();
+        assert_eq!(metadata["vcs_tag"].as_str(), Some("v3.8.0"));
+        let checksums = &metadata["checksums"]["file.txt"];
+        assert_eq!(checksums["etag"].as_str(), Some("\"e1\""));
+        assert_eq!(checksums["sha256"].as_str().map(String::from),
+                   checksum::sha256_file(Path::new(&filename)).ok());
+        assert_eq!(metadata["checksums"]["other.txt"]["etag"].as_str(),
+                   Some("\"e2\""));
+        assert!(metadata["checksums"].get("old.txt").is_none());
+    }
+
+    #[test]
+    fn versions_must_stay_in_the_package() {
+        let server = TestServer::start();
+        assert!(backend(&server).list_files("../other", true).is_err());
+        assert!(server.requests().is_empty());
+    }
+}
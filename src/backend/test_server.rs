@@ -0,0 +1,173 @@
+//! A local HTTP server standing in for the API of a backend in tests.
+//!
+//! Responses are registered per method and path; the requests received
+//! are recorded so tests can check what the backend sent.
+
+use hyper::server::{Handler, Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use std::io::Read;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+struct Route {
+    method: String,
+    path: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    once: bool,
+}
+
+/// A request received by the server.
+#[derive(Clone, Debug)]
+pub struct Recorded {
+    pub method: String,
+    /// The path and query of the request.
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_str())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<Recorded>,
+}
+
+struct StateHandler(Arc<Mutex<State>>);
+
+pub struct TestServer {
+    /// The base URL of the server, without a trailing slash.
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        let state = Arc::new(Mutex::new(State::default()));
+        // Without keep-alive, idle connections of the clients don't hold
+        // the server threads.
+        let mut server = Server::http("127.0.0.1:0").unwrap();
+        server.keep_alive(None);
+        let listening = server.handle(StateHandler(state.clone())).unwrap();
+        let url = format!("http://{}", listening.socket);
+        // Dropping `Listening` waits for the server threads, which never
+        // stop: leave them running until the test process exits.
+        mem::forget(listening);
+        TestServer {
+            url: url,
+            state: state,
+        }
+    }
+
+    /// Answers every `method` request for `path` with `status` and
+    /// `body`. `path` matches the request path, or its path and query if
+    /// it contains a `?`. Routes added later take precedence.
+    pub fn on(&self, method: &str, path: &str, status: u16, body: &str)
+        -> &TestServer
+    {
+        self.add(method, path, status, &[], body.as_bytes(), false)
+    }
+
+    /// Like `on()`, for the next matching request only.
+    pub fn once(&self, method: &str, path: &str, status: u16, body: &str)
+        -> &TestServer
+    {
+        self.add(method, path, status, &[], body.as_bytes(), true)
+    }
+
+    pub fn add(&self,
+               method: &str,
+               path: &str,
+               status: u16,
+               headers: &[(&str, &str)],
+               body: &[u8],
+               once: bool)
+        -> &TestServer
+    {
+        self.state.lock().unwrap().routes.push(Route {
+            method: String::from(method),
+            path: String::from(path),
+            status: status,
+            headers: headers.iter()
+                .map(|&(n, v)| (String::from(n), String::from(v)))
+                .collect(),
+            body: body.to_vec(),
+            once: once,
+        });
+        self
+    }
+
+    /// Returns the requests received so far.
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Returns the path and query of the requests received so far with
+    /// `method`.
+    pub fn uris(&self, method: &str) -> Vec<String> {
+        self.requests().into_iter()
+            .filter(|r| r.method == method)
+            .map(|r| r.uri)
+            .collect()
+    }
+}
+
+impl Handler for StateHandler {
+    fn handle(&self, mut request: Request, mut response: Response) {
+        let uri = match request.uri {
+            RequestUri::AbsolutePath(ref uri) => uri.clone(),
+            ref uri                           => uri.to_string(),
+        };
+        let mut body = vec![];
+        let _ = request.read_to_end(&mut body);
+        let recorded = Recorded {
+            method: request.method.to_string(),
+            uri: uri.clone(),
+            headers: request.headers.iter()
+                .map(|h| (String::from(h.name()), h.value_string()))
+                .collect(),
+            body: body,
+        };
+
+        let mut state = self.0.lock().unwrap();
+        let path = uri.split('?').next().unwrap_or("");
+        let index = state.routes.iter().rposition(|r| {
+            r.method == recorded.method &&
+                (r.path == uri || (!r.path.contains('?') && r.path == path))
+        });
+        state.requests.push(recorded);
+        let route = match index {
+            Some(index) if state.routes[index].once => {
+                Some(state.routes.remove(index))
+            }
+            Some(index) => Some(state.routes[index].clone()),
+            None        => None,
+        };
+        drop(state);
+
+        let route = match route {
+            Some(route) => route,
+            None        => {
+                *response.status_mut() = StatusCode::NotFound;
+                let _ = response.send(b"");
+                return;
+            }
+        };
+        *response.status_mut() = StatusCode::from_u16(route.status);
+        for (name, value) in route.headers {
+            response.headers_mut().set_raw(name, vec![value.into_bytes()]);
+        }
+        let _ = response.send(&route.body);
+    }
+}
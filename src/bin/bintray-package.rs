@@ -1,5 +1,7 @@
 extern crate bintray;
+extern crate chrono;
 extern crate clap;
+#[macro_use] extern crate concourse_bintray_resources;
 extern crate env_logger;
 extern crate glob;
 #[macro_use] extern crate log;
@@ -7,16 +9,37 @@ extern crate regex;
 #[macro_use] extern crate serde_derive;
 extern crate serde_json;
 
-use bintray::client::{BintrayClient, BintrayError};
-use bintray::repository::Repository;
-use bintray::package::{Package, PackageMaturity};
-use bintray::version::Version;
-use bintray::content::{self, Content};
+use bintray::client::BintrayError;
+use bintray::content;
 use bintray::utils;
+use chrono::{DateTime, Utc};
 use clap::{App, Arg};
+use concourse_bintray_resources::apt::{AptPackage, AptRepository};
+use concourse_bintray_resources::backend::{Backend, FileRecord, PackageProps,
+                                           RecordChange, UploadOptions,
+                                           VersionRecord, relative_path};
+use concourse_bintray_resources::backend::artifactory::{self,
+                                                        ArtifactoryBackend};
+use concourse_bintray_resources::backend::bintray::BintrayBackend;
+use concourse_bintray_resources::backend::filesystem::FilesystemBackend;
+use concourse_bintray_resources::backend::github::GitHubBackend;
+use concourse_bintray_resources::backend::s3::{S3Backend, S3Credentials};
+use concourse_bintray_resources::checksum;
+use concourse_bintray_resources::deb;
+use concourse_bintray_resources::duration::parse_duration;
+use concourse_bintray_resources::gpg::Keyring;
+use concourse_bintray_resources::package_version;
+use concourse_bintray_resources::parallel::parallel_map;
+use concourse_bintray_resources::retry::RetryPolicy;
+use concourse_bintray_resources::rpm;
+use concourse_bintray_resources::semver_range::SemverRange;
+use concourse_bintray_resources::temp_dir::{self, TempDir};
+use concourse_bintray_resources::version_order::VersionOrder;
+use concourse_bintray_resources::yum::{self, YumPackage};
 use glob::{glob, Pattern};
 use regex::{Regex, NoExpand};
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::env;
 use std::ffi::OsStr;
 use std::fs::{self, File};
@@ -26,16 +49,40 @@ use std::{thread, time};
 
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 struct Source {
-    username: String,
-    api_key: String,
+    backend: Option<BackendType>,
+    username: Option<String>,
+    api_key: Option<String>,
+    root: Option<String>,
+    endpoint: Option<String>,
+    region: Option<String>,
+    bucket: Option<String>,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
     subject: String,
     repository: String,
     package: String,
     gpg_passphrase: Option<String>,
-    version_filter: Option<StringVecOrFile>,
+    gpg_private_key: Option<StringOrFile>,
+    version_filter: Option<VersionFilterOrFile>,
+    version_order: Option<VersionOrder>,
+    retries: Option<RetryPolicy>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+enum BackendType {
+    #[serde(rename = "artifactory")]
+    Artifactory,
+    #[serde(rename = "bintray")]
+    Bintray,
+    #[serde(rename = "filesystem")]
+    Filesystem,
+    #[serde(rename = "github_releases")]
+    GitHubReleases,
+    #[serde(rename = "s3")]
+    S3,
 }
 
 #[derive(Debug, Deserialize)]
@@ -67,6 +114,14 @@ struct InParams {
     local_path: Option<StringOrFile>,
     remote_path: Option<StringOrFile>,
     filter: Option<StringVecOrFile>,
+
+    verify_checksums: Option<bool>,
+    checksum_retries: Option<u64>,
+
+    verify_signatures: Option<bool>,
+    trusted_keys: Option<StringVecOrFile>,
+
+    parallelism: Option<usize>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -82,7 +137,9 @@ struct OutParams {
     local_path: Option<StringOrFile>,
     remote_path: Option<StringOrFile>,
     filter: Option<StringVecOrFile>,
-    version: StringOrFile,
+    version: VersionSource,
+    version_consistency: Option<VersionConsistency>,
+    group_by_version: Option<bool>,
 
     package_props: Option<PackagePropsOutParams>,
     version_props: Option<VersionPropsOutParams>,
@@ -94,10 +151,38 @@ struct OutParams {
     debian_architecture: Option<StringVecOrFile>,
     debian_distribution: Option<StringVecOrFile>,
     debian_component: Option<StringVecOrFile>,
+    yum_metadata_depth: Option<usize>,
 
     show_in_download_list: Option<bool>,
 
+    publish_timeout: Option<u64>,
+    publish_poll_interval: Option<u64>,
+    download_list_timeout: Option<u64>,
+    download_list_poll_interval: Option<u64>,
+
     keep_existing_files: Option<bool>,
+
+    parallelism: Option<usize>,
+
+    targets: Option<Vec<OutTarget>>,
+
+    dry_run: Option<bool>,
+}
+
+/// A package to publish to, with the params which differ from those of
+/// `out`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutTarget {
+    repository: Option<String>,
+    package: Option<String>,
+    filter: Option<StringVecOrFile>,
+    remote_path: Option<StringOrFile>,
+
+    debian_architecture: Option<StringVecOrFile>,
+    debian_distribution: Option<StringVecOrFile>,
+    debian_component: Option<StringVecOrFile>,
+    yum_metadata_depth: Option<usize>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -133,6 +218,18 @@ struct VersionPropsOutParams {
 
     delete: Option<bool>,
     keep_last_n: Option<u64>,
+    keep_newer_than: Option<String>,
+    keep_min: Option<u64>,
+    delete_unpublished_older_than: Option<String>,
+    preview: Option<bool>,
+    max_deletions: Option<u64>,
+}
+
+/// The content of the `metadata.json` file written by `in`.
+#[derive(Serialize)]
+struct InMetadata<'a> {
+    version: &'a VersionRecord,
+    files: &'a [FileRecord],
 }
 
 #[derive(Serialize)]
@@ -149,7 +246,7 @@ struct OutMetadata {
     value: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields,untagged)]
 enum StringOrFile {
     FromString(String),
@@ -164,12 +261,102 @@ enum StringVecOrFile {
     FromFile(FromFile),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 struct FromFile {
     from_file: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields,untagged)]
+enum VersionSource {
+    FromRegex(String),
+    FromFile(FromFile),
+    FromPackage(FromPackage),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FromPackage {
+    from_package: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+enum VersionConsistency {
+    /// The version comes from the first matching file.
+    #[serde(rename = "first")]
+    First,
+    /// All matching files must yield the same version.
+    #[serde(rename = "strict")]
+    Strict,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields,untagged)]
+enum VersionFilterOrFile {
+    FromFile(FromFile),
+    FromFilterVec(Vec<VersionFilter>),
+    FromFilter(VersionFilter),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields,untagged)]
+enum VersionFilter {
+    Glob(String),
+    Regex(RegexVersionFilter),
+    Semver(SemverVersionFilter),
+    Exclude(ExcludeVersionFilter),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegexVersionFilter {
+    regex: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SemverVersionFilter {
+    semver: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExcludeVersionFilter {
+    exclude: Vec<VersionFilter>,
+}
+
+enum VersionMatcher {
+    Glob(Pattern),
+    Regex(Regex),
+    Semver(SemverRange),
+}
+
+struct VersionMatchers {
+    include: Vec<VersionMatcher>,
+    exclude: Vec<VersionMatcher>,
+}
+
+struct RetentionPolicy {
+    keep_last_n: u64,
+    keep_min: u64,
+    keep_newer_than: Option<time::Duration>,
+    delete_unpublished_older_than: Option<time::Duration>,
+}
+
+/// A version created or updated by `out`, with the number of files
+/// uploaded and removed (or which would be, in a dry run).
+struct PublishedVersion {
+    version: VersionRecord,
+    uploaded: usize,
+    removed: usize,
+}
+
+struct VersionAge {
+    age: time::Duration,
+    unpublished: bool,
+}
+
 fn main() {
     /* Initialize logger. */
     env_logger::init().unwrap();
@@ -212,7 +399,7 @@ fn main() {
             let _ = writeln!(
                 &mut std::io::stderr(),
                 "\x1b[31mProgram name unrecognized: {:?}\x1b[0m", program_name);
-            std::process::exit(64);
+            exit(64);
         }
     }
 }
@@ -237,40 +424,69 @@ fn check() {
         Err(e) => { error_out(&BintrayError::Json(e)); }
     };
 
-    let client = BintrayClient::new(
-        Some(input.source.username),
-        Some(input.source.api_key));
-
-    let mut package = Package::new(&input.source.subject,
-                                   &input.source.repository,
-                                   &input.source.package);
+    let backend = create_backend(&input.source);
+    let retries = retry_policy(&input.source);
 
-    match package.get(false, &client) {
-        Ok(()) => { }
-        Err(BintrayError::Io(ref e))
-            if e.kind() == io::ErrorKind::NotFound => { }
-        Err(e) => { error_out(&e) }
-    }
+    // Compile version filters first so errors are reported before we
+    // query the API.
+    let version_filter = compile_version_filters(input.source.version_filter);
 
     // Print the result as JSON on stdout.
-    let result = get_check_result(&package, input.version, input.source.version_filter, &client);
+    let version_order = input.source.version_order
+        .unwrap_or(VersionOrder::Chronological);
+    let result = get_check_result(&*backend,
+                                  input.version,
+                                  &version_filter,
+                                  version_order,
+                                  &retries);
     match serde_json::to_string_pretty(&result) {
         Ok(output) => { println!("{}", output); }
         Err(e)     => { error_out(&BintrayError::Json(e)); }
     };
 }
 
-fn get_check_result(package: &Package,
+fn get_check_result(backend: &dyn Backend,
                     version: Option<CheckVersion>,
-                    version_filter: Option<StringVecOrFile>,
-                    client: &BintrayClient)
+                    version_filter: &VersionMatchers,
+                    version_order: VersionOrder,
+                    retries: &RetryPolicy)
     -> Vec<CheckVersion>
 {
     let only_last = version.is_none();
-    let mut filtered_versions = filter_matching_versions(
-        package.get_versions_starting_at(&version.map(|v| v.version), Some(client)),
-        version_filter
-    );
+    let current_version = version.map(|v| v.version);
+
+    // When versions are not sorted chronologically, a version published
+    // after the current one may still be older. Therefore we need to
+    // look at all versions and skip the older ones after sorting.
+    let since = match version_order {
+        VersionOrder::Chronological => current_version.as_ref()
+            .map(String::as_str),
+        _                           => None,
+    };
+    let versions = match retries.run("List versions",
+                                     || backend.list_versions(since)) {
+        Ok(versions) => versions,
+        // The package doesn't exist yet.
+        Err(BintrayError::Io(ref e))
+            if e.kind() == io::ErrorKind::NotFound => vec![],
+        Err(e) => error_out(&e),
+    };
+
+    let mut filtered_versions = filter_matching_versions(versions,
+                                                         version_filter);
+    if version_order != VersionOrder::Chronological {
+        filtered_versions.sort_by(
+            |a, b| version_order.compare(&a.version, &b.version));
+        match current_version {
+            Some(ref current) => {
+                filtered_versions.retain(|v| {
+                    version_order.compare(&v.version, current) !=
+                        Ordering::Less
+                });
+            }
+            None => { }
+        }
+    }
     if only_last {
         match filtered_versions.pop() {
             None => vec![],
@@ -282,30 +498,113 @@ fn get_check_result(package: &Package,
     }
 }
 
-fn filter_matching_versions(versions: Vec<Version>, version_filter: Option<StringVecOrFile>)
-    -> Vec<CheckVersion>
+/// Returns the most recent version matching the filters, sorted like
+/// `get_check_result()` does, from the names of the versions only.
+fn latest_version(backend: &dyn Backend,
+                  version_filter: &VersionMatchers,
+                  version_order: VersionOrder,
+                  retries: &RetryPolicy)
+    -> Option<String>
 {
+    let mut versions = retries
+        .run("List versions", || backend.list_version_names())
+        .unwrap_or_else(|e| error_out(&e));
+    versions.retain(|v| version_filter.matches(v));
+    if version_order != VersionOrder::Chronological {
+        versions.sort_by(|a, b| version_order.compare(a, b));
+    }
+    versions.pop()
+}
 
-    let globs = version_filter.map_or(
-        vec![String::from("*")],
-        |v| from_string_vec_or_file(&v));
-
+fn filter_matching_versions(versions: Vec<VersionRecord>,
+                            version_filter: &VersionMatchers)
+    -> Vec<CheckVersion>
+{
     versions
         .iter()
-        .filter(|v| version_match_globs(&v, &globs))
+        .filter(|v| version_filter.matches(&v.version))
         .map(version_for_concourse)
         .collect()
 }
 
-fn version_match_globs<T: Borrow<str>>(version: &Version, globs: &[T]) -> bool
+fn compile_version_filters(version_filter: Option<VersionFilterOrFile>)
+    -> VersionMatchers
 {
-    let version_string = &version.version;
-    globs.iter()
-        .any(|g| {
-            let pattern = Pattern::new(g.borrow())
-                .unwrap_or_else(|e| error_out(&e));
-            pattern.matches(&version_string)
-        })
+    let filters = match version_filter {
+        None => vec![],
+        Some(VersionFilterOrFile::FromFilterVec(filters)) => filters,
+        Some(VersionFilterOrFile::FromFilter(filter)) => vec![filter],
+        Some(VersionFilterOrFile::FromFile(fileparams)) => {
+            // A file contains one glob pattern per line.
+            let input = StringVecOrFile::FromFile(fileparams);
+            from_string_vec_or_file(&input).into_iter()
+                .map(VersionFilter::Glob)
+                .collect()
+        }
+    };
+
+    let mut matchers = VersionMatchers { include: vec![], exclude: vec![] };
+    for filter in filters {
+        match filter {
+            VersionFilter::Exclude(exclude) => {
+                for filter in exclude.exclude {
+                    matchers.exclude.push(compile_version_filter(filter));
+                }
+            }
+            filter => {
+                matchers.include.push(compile_version_filter(filter));
+            }
+        }
+    }
+
+    matchers
+}
+
+fn compile_version_filter(filter: VersionFilter) -> VersionMatcher {
+    match filter {
+        VersionFilter::Glob(glob) => {
+            VersionMatcher::Glob(Pattern::new(&glob)
+                .unwrap_or_else(|e| error_out_with_message(
+                        &format!("Invalid version filter \"{}\"", glob),
+                        e)))
+        }
+        VersionFilter::Regex(regex) => {
+            VersionMatcher::Regex(Regex::new(&regex.regex)
+                .unwrap_or_else(|e| error_out_with_message(
+                        &format!("Invalid version filter regex \"{}\"",
+                                 regex.regex),
+                        e)))
+        }
+        VersionFilter::Semver(semver) => {
+            VersionMatcher::Semver(SemverRange::parse(&semver.semver)
+                .unwrap_or_else(|e| error_out(&e)))
+        }
+        VersionFilter::Exclude(_) => {
+            error_out(&io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Version filter `exclude` can't be nested"));
+        }
+    }
+}
+
+impl VersionMatcher {
+    fn matches(&self, version: &str) -> bool {
+        match *self {
+            VersionMatcher::Glob(ref pattern) => pattern.matches(version),
+            VersionMatcher::Regex(ref re)     => re.is_match(version),
+            VersionMatcher::Semver(ref range) => range.matches(version),
+        }
+    }
+}
+
+impl VersionMatchers {
+    /// A version matches if it matches any of the included patterns (or
+    /// if there are none) and none of the excluded ones.
+    fn matches(&self, version: &str) -> bool {
+        (self.include.is_empty() ||
+         self.include.iter().any(|m| m.matches(version))) &&
+            !self.exclude.iter().any(|m| m.matches(version))
+    }
 }
 
 // -------------------------------------------------------------------
@@ -331,26 +630,32 @@ fn in_() {
         local_path: None,
         remote_path: None,
         filter: None,
+        verify_checksums: None,
+        checksum_retries: None,
+        verify_signatures: None,
+        trusted_keys: None,
+        parallelism: None,
     });
 
     /* We use version "<DELETED>" as a special version after a version or
-     * a package was deleted in `out`. */
+     * a package was deleted in `out`, and "<DRY-RUN>" after a dry run. */
     match input.version.as_ref() {
         Some(version) => {
-            if version.version == "<DELETED>" {
+            if version.version == "<DELETED>" ||
+                version.version == "<DRY-RUN>" {
                 let _ = writeln!(&mut std::io::stderr(),
                     "Getting special version {} is a no-op; returning it as is",
                     version.version);
 
                 let result = OutResult {
                     version: CheckVersion {
-                        version: String::from("<DELETED>"),
+                        version: version.version.clone(),
                         updated: None,
                     },
                     metadata: vec![],
                 };
                 let output = serde_json::to_string_pretty(&result)
-                    .expect("Failed to convert <DELETED> version to JSON");
+                    .expect("Failed to convert special version to JSON");
 
                 println!("{}", output);
                 return;
@@ -359,40 +664,48 @@ fn in_() {
         None => {}
     }
 
-    let client = BintrayClient::new(
-        Some(input.source.username),
-        Some(input.source.api_key));
-
-    let mut package = Package::new(&input.source.subject,
-                                   &input.source.repository,
-                                   &input.source.package);
-
-    match package.get(false, &client) {
-        Ok(()) => { }
-        Err(e) => { error_out(&e) }
-    }
+    let source = &input.source;
+    let backend = create_backend(source);
+    let retries = retry_policy(source);
 
-    // Create or update version properties with input params.
+    // Without a version, get the one `check` would return.
     let version_string = match input.version {
         Some(version) => version.version,
         None => {
-            package.get_latest_version(Some(&client))
+            let version_filter = compile_version_filters(
+                source.version_filter.clone());
+            latest_version(&*backend,
+                           &version_filter,
+                           source.version_order
+                               .unwrap_or(VersionOrder::Chronological),
+                           &retries)
                 .unwrap_or_else(|| error_out(&io::Error::new(
                     io::ErrorKind::NotFound,
-                    format!("The package {} has no version",
-                            package))))
-                .version
+                    format!("The package {} has no matching version",
+                            package_name(source)))))
         }
     };
-    let mut version = Version::new(&input.source.subject,
-                                   &input.source.repository,
-                                   &input.source.package,
-                                   &version_string);
-
-    match version.get(false, &client) {
-        Ok(()) => { }
-        Err(e) => { error_out(&e) }
-    }
+    let version = retries.run("Get version",
+                              || backend.get_version(&version_string))
+        .unwrap_or_else(|e| error_out(&e))
+        .unwrap_or_else(|| error_out(&io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("The version {} of package {} doesn't exist",
+                    version_string, package_name(source)))));
+
+    // Import the trusted keys before entering local_path: their file is
+    // relative to the destination directory.
+    let destination = env::current_dir()
+        .unwrap_or_else(|e| error_out(&BintrayError::from(e)));
+    let keyring = match params.verify_signatures {
+        Some(true) => match params.trusted_keys {
+            Some(ref keys) => Some(trusted_keyring(keys)),
+            None => error_out(&io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "`verify_signatures` requires `trusted_keys`")),
+        },
+        _ => None,
+    };
 
     let local_path = params.local_path
         .map_or(String::new(), |v| from_string_or_file(&v));
@@ -419,11 +732,90 @@ fn in_() {
     let globs = params.filter.map_or(
         vec![String::from("**/*")],
         |v| from_string_vec_or_file(&v));
-    let files = version.list_files(true, &client)
+    let checksum_retries = match params.verify_checksums {
+        Some(false) => None,
+        _           => Some(params.checksum_retries.unwrap_or(3)),
+    };
+    let parallelism = params.parallelism.unwrap_or(4).max(1);
+    let all_files = retries.run("List files",
+                                || backend.list_files(&version_string, true))
         .unwrap_or_else(|e| error_out(&e));
-    files.iter()
+    let mut files = all_files.iter()
         .filter(|f| does_file_match_globs(&f, &remote_path, &globs))
-        .fold((), |acc, f| { download_file(&f, &remote_path, &client); acc });
+        .cloned()
+        .collect::<Vec<FileRecord>>();
+
+    // When signatures are verified, they are downloaded along with the
+    // files they sign, whatever the filter.
+    let signatures = match keyring {
+        Some(_) => {
+            files.retain(|f| !is_signature(f));
+            find_signatures(&files, &all_files)
+        }
+        None => vec![],
+    };
+    let downloaded = files.iter()
+        .chain(signatures.iter())
+        .cloned()
+        .collect::<Vec<FileRecord>>();
+    for file in &downloaded {
+        let filename = Path::new(&local_path)
+            .join(filename_relative_to(file, &remote_path).unwrap());
+        if is_metadata_file(&filename) {
+            error_out(&io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} would be overwritten by the metadata of \
+                             the version; set `local_path` to download \
+                             the files to a subdirectory",
+                            filename.display())));
+        }
+    }
+    download_files(downloaded.clone(),
+                   &version_string,
+                   &remote_path,
+                   checksum_retries,
+                   parallelism,
+                   &retries,
+                   &input.source);
+
+    match keyring {
+        Some(ref keyring) => {
+            let _ = writeln!(&mut std::io::stderr(), "");
+            for (file, signature) in files.iter().zip(signatures.iter()) {
+                match verify_signature(file, signature, &remote_path,
+                                       keyring) {
+                    Ok(())  => { }
+                    Err(e) => {
+                        // The files which were not verified yet can't be
+                        // trusted either: nothing is left behind.
+                        remove_downloaded_files(&downloaded, &remote_path);
+                        let filename =
+                            filename_relative_to(file, &remote_path).unwrap();
+                        error_out_with_filename(
+                            &filename.to_string_lossy(), e);
+                    }
+                }
+            }
+        }
+        None => { }
+    }
+
+    // Verified signatures are removed: only the files they sign are left
+    // in the destination directory and listed in its metadata.
+    let downloaded = match keyring {
+        Some(_) => files,
+        None    => downloaded,
+    };
+
+    // Downloaded files are listed relative to the destination directory.
+    let downloaded = downloaded.into_iter()
+        .map(|file| FileRecord {
+            path: Path::new(&local_path)
+                .join(filename_relative_to(&file, &remote_path).unwrap()),
+            ..file
+        })
+        .collect::<Vec<FileRecord>>();
+    write_in_metadata(&destination, &version, &downloaded);
 
     // Print the result as JSON on stdout.
     let result = get_out_result(&version);
@@ -433,12 +825,54 @@ fn in_() {
     };
 }
 
-fn does_file_match_globs<T: Borrow<str>>(content: &Content,
+/// The files written to the destination directory by
+/// `write_in_metadata()`.
+const METADATA_FILES: &'static [&'static str] =
+    &["version", "released", "vcs_tag", "metadata.json"];
+
+/// Tells if `filename`, relative to the destination directory, is one of
+/// the metadata files of the version.
+fn is_metadata_file(filename: &Path) -> bool {
+    let filename = relative_path(filename);
+    METADATA_FILES.iter().any(|name| filename == Path::new(name))
+}
+
+/// Writes the `version`, `released` and `vcs_tag` files and the
+/// `metadata.json` file to the destination directory, so following tasks
+/// don't need to parse file names.
+fn write_in_metadata(destination: &Path,
+                     version: &VersionRecord,
+                     files: &[FileRecord])
+{
+    let metadata = InMetadata {
+        version: version,
+        files: files,
+    };
+    let json = serde_json::to_string_pretty(&metadata)
+        .unwrap_or_else(|e| error_out(&BintrayError::Json(e)));
+
+    let empty = String::new();
+    let contents = [
+        ("version", &version.version),
+        ("released", version.released.as_ref().unwrap_or(&empty)),
+        ("vcs_tag", version.vcs_tag.as_ref().unwrap_or(&empty)),
+        ("metadata.json", &json),
+    ];
+    for &(name, content) in contents.iter() {
+        let filename = destination.join(name);
+        File::create(&filename)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .unwrap_or_else(|e| error_out_with_filename(
+                    &filename.to_string_lossy(), e));
+    }
+}
+
+fn does_file_match_globs<T: Borrow<str>>(file: &FileRecord,
                                          remote_path: &PathBuf,
                                          globs: &[T])
     -> bool
 {
-    match filename_relative_to(content, remote_path) {
+    match filename_relative_to(file, remote_path) {
         Some(filename) => {
             let filename = String::from(filename.to_string_lossy());
             globs.iter()
@@ -452,20 +886,44 @@ fn does_file_match_globs<T: Borrow<str>>(content: &Content,
     }
 }
 
-fn filename_relative_to<T: AsRef<Path>>(content: &Content, remote_path: T)
+fn filename_relative_to<T: AsRef<Path>>(file: &FileRecord, remote_path: T)
     -> Option<PathBuf>
 {
-    match content.path.strip_prefix(remote_path.as_ref()) {
+    match file.path.strip_prefix(remote_path.as_ref()) {
         Ok(filename) => Some(PathBuf::from(filename)),
         Err(_)       => None,
     }
 }
 
-fn download_file<T: AsRef<Path>>(content: &Content,
+fn download_files(files: Vec<FileRecord>,
+                  version_string: &str,
+                  remote_path: &PathBuf,
+                  checksum_retries: Option<u64>,
+                  parallelism: usize,
+                  retries: &RetryPolicy,
+                  source: &Source)
+{
+    // If a download fails, `download_file()` exits the whole process so
+    // the remaining downloads are aborted too.
+    parallel_map(files,
+                 parallelism,
+                 || create_backend(source),
+                 |backend, file| download_file(&**backend,
+                                               version_string,
+                                               &file,
+                                               remote_path,
+                                               checksum_retries,
+                                               retries));
+}
+
+fn download_file<T: AsRef<Path>>(backend: &dyn Backend,
+                                 version_string: &str,
+                                 file: &FileRecord,
                                  remote_path: T,
-                                 client: &BintrayClient)
+                                 checksum_retries: Option<u64>,
+                                 retries: &RetryPolicy)
 {
-    let filename = filename_relative_to(content, remote_path).unwrap();
+    let filename = filename_relative_to(file, remote_path).unwrap();
 
     let _ = writeln!(&mut std::io::stderr(),
         "\x1b[32mDownload file:\x1b[0m {}", filename.display());
@@ -476,8 +934,112 @@ fn download_file<T: AsRef<Path>>(content: &Content,
         }
         None => { }
     }
-    content.download(&filename, client)
-        .unwrap_or_else(|e| error_out(&e));
+
+    let mut attempts = 0;
+    loop {
+        retries.run(&format!("Download {}", filename.display()),
+                    || backend.download_file(version_string, file, &filename))
+            .unwrap_or_else(|e| error_out(&e));
+
+        let checksum_retries = match checksum_retries {
+            Some(retries) => retries,
+            None          => break,
+        };
+        match verify_checksum(file, &filename) {
+            Ok(()) => break,
+            Err(e) => {
+                if attempts >= checksum_retries {
+                    error_out_with_filename(
+                        &filename.to_string_lossy(), e);
+                }
+                attempts += 1;
+                let _ = writeln!(&mut std::io::stderr(),
+                    "\x1b[33m{}: {}; retrying download ({}/{})\x1b[0m",
+                    filename.display(), e, attempts, checksum_retries);
+            }
+        }
+    }
+}
+
+fn is_signature(file: &FileRecord) -> bool {
+    file.path.extension() == Some(OsStr::new("asc"))
+}
+
+/// Returns the `.asc` signature of each file, in the same order. Exits
+/// with the list of unsigned files if some signatures are missing.
+fn find_signatures(files: &[FileRecord], all_files: &[FileRecord])
+    -> Vec<FileRecord>
+{
+    let mut signatures = vec![];
+    let mut unsigned = vec![];
+    for file in files {
+        let mut path = file.path.clone().into_os_string();
+        path.push(".asc");
+        let path = PathBuf::from(path);
+        match all_files.iter().find(|f| same_remote_path(&f.path, &path)) {
+            Some(signature) => signatures.push(signature.clone()),
+            None            => unsigned.push(file),
+        }
+    }
+
+    if !unsigned.is_empty() {
+        error_out_with_files("Signature missing for some files", &unsigned);
+    }
+    signatures
+}
+
+/// Checks the signature of a downloaded file. The signature is removed
+/// once verified.
+fn verify_signature<T: AsRef<Path>>(file: &FileRecord,
+                                    signature: &FileRecord,
+                                    remote_path: T,
+                                    keyring: &Keyring)
+    -> io::Result<()>
+{
+    let filename = filename_relative_to(file, &remote_path).unwrap();
+    let signature = filename_relative_to(signature, &remote_path).unwrap();
+
+    let _ = writeln!(&mut std::io::stderr(),
+        "\x1b[32mVerify signature:\x1b[0m {}", filename.display());
+    keyring.verify(&filename, &signature)?;
+    fs::remove_file(&signature)
+}
+
+/// Removes the downloaded files and signatures which are still in the
+/// destination directory.
+fn remove_downloaded_files<T: AsRef<Path>>(files: &[FileRecord],
+                                           remote_path: T)
+{
+    for file in files {
+        let filename = filename_relative_to(file, &remote_path).unwrap();
+        let _ = fs::remove_file(&filename);
+    }
+}
+
+fn verify_checksum(file: &FileRecord, filename: &Path) -> io::Result<()> {
+    // SHA-256 is preferred over SHA-1 if the backend provides both.
+    let (algorithm, expected, actual) =
+        match (file.sha256.as_ref(), file.sha1.as_ref()) {
+            (Some(expected), _) =>
+                ("SHA-256", expected, checksum::sha256_file(filename)?),
+            (None, Some(expected)) =>
+                ("SHA-1", expected, checksum::sha1_file(filename)?),
+            (None, None) => {
+                let _ = writeln!(&mut std::io::stderr(),
+                    "\x1b[33m{}: no checksum available; not verified\x1b[0m",
+                    filename.display());
+                return Ok(());
+            }
+        };
+
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} mismatch (expected {}, got {})",
+                        algorithm, expected, actual)))
+    }
 }
 
 // -------------------------------------------------------------------
@@ -500,9 +1062,7 @@ fn out() {
         Err(e) => { error_out(&BintrayError::Json(e)); }
     };
 
-    let client = BintrayClient::new(
-        Some(input.source.username.clone()),
-        Some(input.source.api_key.clone()));
+    let retries = retry_policy(&input.source);
 
     let delete_package = match input.params.package_props.as_ref() {
         Some(v) => match v.delete {
@@ -519,18 +1079,25 @@ fn out() {
         None => false,
     };
 
+    // With `targets`, the backend of the source is only used to delete:
+    // each target gets its own.
     if delete_package || delete_version {
-        out_delete(client, input, delete_package);
+        let backend = create_backend(&input.source);
+        out_delete(&*backend, input, delete_package, &retries);
     } else {
-        out_publish(client, input);
+        out_publish(input, &retries);
     }
 }
 
-fn out_publish(client: BintrayClient, input: OutInput)
-{
+fn out_publish(input: OutInput, retries: &RetryPolicy) {
+    // Import the signing key first: a bad key should fail the step before
+    // anything is uploaded.
+    let keyring = input.source.gpg_private_key.as_ref()
+        .map(|key| signing_keyring(key, &input.source));
+
     // Enter local_path, if one was specified.
-    let local_path = input.params.local_path
-        .map_or(String::new(), |v| from_string_or_file(&v));
+    let local_path = input.params.local_path.as_ref()
+        .map_or(String::new(), from_string_or_file);
     let _ = writeln!(&mut std::io::stderr(),
         "\x1b[32mLocal path:\x1b[0m\n    {}\n", local_path);
 
@@ -539,180 +1106,595 @@ fn out_publish(client: BintrayClient, input: OutInput)
             .unwrap_or_else(|e| error_out(&BintrayError::from(e)));
     }
 
-    let mut repo = Repository::new(&input.source.subject,
-                                   &input.source.repository);
+    let dry_run = input.params.dry_run.unwrap_or(false);
+    if dry_run {
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[33mDry run: nothing will be changed\x1b[0m\n");
+    }
+
+    // Find all files to include in the package/version(s).
+    let files = find_files(input.params.filter.as_ref());
+    let consistency = input.params.version_consistency
+        .unwrap_or(VersionConsistency::First);
+    let versions = if input.params.group_by_version.unwrap_or(false) {
+        if consistency == VersionConsistency::Strict {
+            error_out(&io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "`version_consistency: strict` can't be used with \
+                     `group_by_version`"));
+        }
+        group_files_by_version(&input.params.version,
+                               input.source.version_order,
+                               files)
+    } else {
+        let version_string = determine_version(&input.params.version,
+                                               consistency,
+                                               &files);
+        vec![(version_string, files)]
+    };
+
+    // Without `targets`, the package of the source is the only target.
+    let default_target = OutTarget::default();
+    let targets = match input.params.targets {
+        Some(ref targets) => targets.iter().collect::<Vec<&OutTarget>>(),
+        None              => vec![&default_target],
+    };
+    let targets = targets.into_iter()
+        .map(|target| {
+            let source = target_source(&input.source, target);
+            let versions = target_versions(&source, target, &versions);
+            (source, target, versions)
+        })
+        .collect::<Vec<(Source, &OutTarget, Vec<(String, Vec<PathBuf>)>)>>();
+
+    // Versions are sorted from the lowest to the highest, so the highest
+    // one is also the most recent and the step returns it: some target
+    // must publish it, which is checked before anything is changed.
+    let (highest, others) = versions.split_last().unwrap();
+    let publishes_highest = targets.iter()
+        .flat_map(|&(_, _, ref versions)| versions.iter())
+        .any(|v| v.0 == highest.0);
+    if !publishes_highest {
+        error_out(&io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No target has files for version {}", highest.0)));
+    }
+
+    let mut results = vec![];
+    for (source, target, versions) in targets {
+        let published = publish_target(&*create_backend(&source),
+                                       &source,
+                                       &input.params,
+                                       target,
+                                       &versions,
+                                       keyring.as_ref(),
+                                       dry_run,
+                                       retries);
+        results.push((source, published));
+    }
+
+    // The record of the highest version comes from the first target which
+    // published it.
+    let published = results.iter()
+        .flat_map(|&(_, ref published)| published.iter())
+        .collect::<Vec<&PublishedVersion>>();
+    let version = published.iter()
+        .find(|p| p.version.version == highest.0)
+        .map(|p| &p.version)
+        .unwrap();
+
+    let mut result = if dry_run {
+        OutResult {
+            version: CheckVersion {
+                version: String::from("<DRY-RUN>"),
+                updated: None,
+            },
+            metadata: vec![
+                OutMetadata {
+                    name: String::from("Version"),
+                    value: highest.0.clone(),
+                },
+                OutMetadata {
+                    name: String::from("Files to upload"),
+                    value: published.iter()
+                        .map(|p| p.uploaded)
+                        .sum::<usize>()
+                        .to_string(),
+                },
+                OutMetadata {
+                    name: String::from("Files to remove"),
+                    value: published.iter()
+                        .map(|p| p.removed)
+                        .sum::<usize>()
+                        .to_string(),
+                },
+            ],
+        }
+    } else {
+        get_out_result(version)
+    };
+    if !others.is_empty() {
+        result.metadata.push(OutMetadata {
+            name: String::from("Other versions"),
+            value: others.iter()
+                .map(|v| v.0.as_str())
+                .collect::<Vec<&str>>()
+                .join(", "),
+        });
+    }
+    if input.params.targets.is_some() {
+        for &(ref source, ref published) in results.iter() {
+            let value = if published.is_empty() {
+                String::from("nothing to publish")
+            } else {
+                published.iter()
+                    .map(|p| format!("{}: {} file(s) {}, {} {}",
+                                     p.version.version,
+                                     p.uploaded,
+                                     if dry_run { "to upload" }
+                                     else { "uploaded" },
+                                     p.removed,
+                                     if dry_run { "to remove" }
+                                     else { "removed" }))
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            };
+            result.metadata.push(OutMetadata {
+                name: format!("Target {}", package_name(source)),
+                value: value,
+            });
+        }
+    }
+
+    // Print the result as JSON on stdout.
+    match serde_json::to_string_pretty(&result) {
+        Ok(output) => { println!("{}", output); }
+        Err(e)     => { error_out(&BintrayError::Json(e)); }
+    };
+}
+
+/// Returns the source of the package a target publishes to.
+fn target_source(source: &Source, target: &OutTarget) -> Source {
+    Source {
+        repository: target.repository.clone()
+            .unwrap_or_else(|| source.repository.clone()),
+        package: target.package.clone()
+            .unwrap_or_else(|| source.package.clone()),
+        ..source.clone()
+    }
+}
+
+/// Returns the versions a target publishes, with the files of each
+/// version which match the filter of the target. Versions without such
+/// files are skipped.
+fn target_versions(source: &Source,
+                   target: &OutTarget,
+                   versions: &[(String, Vec<PathBuf>)])
+    -> Vec<(String, Vec<PathBuf>)>
+{
+    let patterns = match target.filter {
+        Some(ref filter) => from_string_vec_or_file(filter).iter()
+            .map(|g| Pattern::new(g).unwrap_or_else(|e| error_out(&e)))
+            .collect::<Vec<Pattern>>(),
+        None => return versions.to_vec(),
+    };
+    versions.iter()
+        .filter_map(|&(ref version_string, ref files)| {
+            let files = files.iter()
+                .filter(|f| patterns.iter().any(|p| p.matches_path(f)))
+                .cloned()
+                .collect::<Vec<PathBuf>>();
+            if files.is_empty() {
+                let _ = writeln!(&mut std::io::stderr(),
+                    "\x1b[33mNo file matches the filter of {} for version \
+                     {}: skipped\x1b[0m\n",
+                    package_name(source), version_string);
+                return None;
+            }
+            Some((version_string.clone(), files))
+        })
+        .collect()
+}
 
-    match repo.exists(&client) {
+/// Creates or updates the package of a target, then publishes each of
+/// the versions returned by `target_versions()`.
+fn publish_target(backend: &dyn Backend,
+                  source: &Source,
+                  params: &OutParams,
+                  target: &OutTarget,
+                  versions: &[(String, Vec<PathBuf>)],
+                  keyring: Option<&Keyring>,
+                  dry_run: bool,
+                  retries: &RetryPolicy)
+    -> Vec<PublishedVersion>
+{
+    match retries.run("Get repository", || backend.repository_exists()) {
         Ok(true) => {}
         Ok(false) => {
             error_out(&io::Error::new(
                     io::ErrorKind::NotFound,
-                    format!("The repository {} doesn't exist",
-                            repo)));
+                    format!("The repository {}/{} doesn't exist",
+                            source.subject, source.repository)));
         }
         Err(e) => { error_out(&e); }
     };
 
     // Create or update package properties with input params.
-    let _ = update_package(input.params.package_props,
-                           &input.source,
-                           &client);
-
-    // Find all files to include in the package/version.
-    let files = find_files(input.params.filter);
-    let version_string = determine_version(input.params.version, &files);
+    update_package(params.package_props.as_ref(),
+                   source,
+                   dry_run,
+                   backend,
+                   retries);
+
+    versions.iter()
+        .map(|&(ref version_string, ref files)| {
+            publish_version(backend,
+                            source,
+                            params,
+                            target,
+                            version_string,
+                            files.clone(),
+                            keyring,
+                            dry_run,
+                            retries)
+        })
+        .collect()
+}
 
+/// Creates or updates the version, uploads its files, removes stale ones
+/// and publishes it. In a dry run, only prints what would be done.
+fn publish_version(backend: &dyn Backend,
+                   source: &Source,
+                   params: &OutParams,
+                   target: &OutTarget,
+                   version_string: &str,
+                   files: Vec<PathBuf>,
+                   keyring: Option<&Keyring>,
+                   dry_run: bool,
+                   retries: &RetryPolicy)
+    -> PublishedVersion
+{
     // Create or update version properties with input params.
-    let mut version = update_version(input.params.version_props,
-                                     &input.source,
-                                     &version_string,
-                                     &client);
-
-    let mut old_files = version.list_files(true, &client)
-        .unwrap_or_else(|e| error_out(&e));
+    let version = update_version(params.version_props.as_ref(),
+                                 source,
+                                 version_string,
+                                 dry_run,
+                                 backend,
+                                 retries);
+
+    let mut old_files = match retries.run(
+        "List files", || backend.list_files(version_string, true)) {
+        Ok(files) => files,
+        // In a dry run, the version may not exist yet.
+        Err(BintrayError::Io(ref e))
+            if dry_run && e.kind() == io::ErrorKind::NotFound => vec![],
+        Err(e) => error_out(&e),
+    };
 
     // Upload all files.
-    let publish = match input.params.publish {
+    let publish = match params.publish {
         Some(v) => v,
         None    => true,
     };
-    let override_ = match input.params.override_ {
+    let override_ = match params.override_ {
         Some(v) => v,
         None    => true,
     };
-    let gpg_passphrase = input.source.gpg_passphrase
-        .as_ref().map(String::as_str);
-    let debian_architecture = input.params.debian_architecture
-        .map(|v| from_string_vec_or_file(&v))
+    let debian_architecture = target.debian_architecture.as_ref()
+        .or(params.debian_architecture.as_ref())
+        .map(from_string_vec_or_file)
         .unwrap_or(vec![]);
-    let debian_distribution = input.params.debian_distribution
-        .map(|v| from_string_vec_or_file(&v))
+    let debian_distribution = target.debian_distribution.as_ref()
+        .or(params.debian_distribution.as_ref())
+        .map(from_string_vec_or_file)
         .unwrap_or(vec![]);
-    let debian_component = input.params.debian_component
-        .map(|v| from_string_vec_or_file(&v))
+    let debian_component = target.debian_component.as_ref()
+        .or(params.debian_component.as_ref())
+        .map(from_string_vec_or_file)
         .unwrap_or(vec![]);
 
     let re = Regex::new(r"\$VERSION\b").unwrap();
-    let remote_path = input.params.remote_path
-        .map_or(String::new(), |v| from_string_or_file(&v));
-    let remote_path = re.replace_all(&remote_path, NoExpand(&version_string));
+    let remote_path = target.remote_path.as_ref()
+        .or(params.remote_path.as_ref())
+        .map_or(String::new(), from_string_or_file);
+    let remote_path = re.replace_all(&remote_path, NoExpand(version_string));
     let _ = writeln!(&mut std::io::stderr(),
         "\x1b[32mRemote path:\x1b[0m\n    {}\n", remote_path);
 
-    let files = files.iter()
-        .map(|filename| upload_file(&filename,
-                                    &remote_path,
-                                    publish,
-                                    override_,
-                                    gpg_passphrase,
-                                    &debian_architecture,
-                                    &debian_distribution,
-                                    &debian_component,
-                                    &version,
-                                    &client))
-        .collect::<Vec<Content>>();
-    let _ = writeln!(&mut std::io::stderr(), "");
-
-    let keep_existing_files = match input.params.keep_existing_files {
+    let keep_existing_files = match params.keep_existing_files {
         Some(v) => v,
         None    => false,
     };
-    if ! keep_existing_files {
+
+    if dry_run {
+        let (uploaded, removed) = out_publish_dry_run(&files,
+                                                      &remote_path,
+                                                      old_files,
+                                                      keep_existing_files);
+        return PublishedVersion {
+            version: version,
+            uploaded: uploaded,
+            removed: removed,
+        };
+    }
+
+    let upload_options = UploadOptions {
+        publish: publish,
+        override_: override_,
+        debian_architecture: &debian_architecture,
+        debian_distribution: &debian_distribution,
+        debian_component: &debian_component,
+    };
+
+    let debs = files.iter()
+        .filter(|f| f.extension() == Some(OsStr::new("deb")))
+        .cloned()
+        .collect::<Vec<PathBuf>>();
+    let rpms = files.iter()
+        .filter(|f| f.extension() == Some(OsStr::new("rpm")))
+        .cloned()
+        .collect::<Vec<PathBuf>>();
+
+    // Sign files before uploading them, so a wrong key or passphrase
+    // doesn't leave a partial upload behind.
+    let signatures = keyring
+        .map(|keyring| sign_files(&files, &remote_path, keyring));
+
+    // Files are uploaded concurrently but the returned list follows the
+    // order of `files`.
+    let parallelism = params.parallelism.unwrap_or(4);
+    let source = source;
+    let mut files = parallel_map(
+        files,
+        parallelism,
+        || create_backend(source),
+        |backend, filename| upload_file(&**backend,
+                                        version_string,
+                                        &filename,
+                                        &remote_path,
+                                        &upload_options,
+                                        retries));
+    let _ = writeln!(&mut std::io::stderr(), "");
+
+    match signatures {
+        Some((dir, signatures)) => {
+            let signatures = upload_generated_files(backend,
+                                                    version_string,
+                                                    "signature",
+                                                    dir.path(),
+                                                    &signatures,
+                                                    &upload_options,
+                                                    retries);
+            files.extend(signatures);
+        }
+        None => { }
+    }
+
+    // Bintray builds the APT and YUM indexes itself; other backends get
+    // indexes generated from the packages of the version: the uploaded
+    // ones and, with `keep_existing_files`, the ones already stored.
+    if !backend.builds_indexes() && !debs.is_empty() {
+        let (_dir, mut packages) = download_kept_packages(backend,
+                                                          version_string,
+                                                          "deb",
+                                                          &old_files,
+                                                          &files,
+                                                          keep_existing_files,
+                                                          retries);
+        packages.extend(debs.iter().map(|filename| {
+            (filename.clone(), remote_content(filename, &remote_path).path)
+        }));
+        let indexes = upload_apt_indexes(backend,
+                                         version_string,
+                                         &packages,
+                                         &upload_options,
+                                         source,
+                                         keyring,
+                                         retries);
+        files.extend(indexes);
+    }
+    if !backend.builds_indexes() && !rpms.is_empty() {
+        let (_dir, mut packages) = download_kept_packages(backend,
+                                                          version_string,
+                                                          "rpm",
+                                                          &old_files,
+                                                          &files,
+                                                          keep_existing_files,
+                                                          retries);
+        packages.extend(rpms.iter().map(|filename| {
+            (filename.clone(), remote_content(filename, &remote_path).path)
+        }));
+        let indexes = upload_yum_repodata(
+            backend,
+            version_string,
+            &packages,
+            target.yum_metadata_depth
+                .or(params.yum_metadata_depth)
+                .unwrap_or(0),
+            &upload_options,
+            keyring,
+            retries);
+        files.extend(indexes);
+    }
+
+    let uploaded = files.len();
+    if keep_existing_files {
+        old_files.clear();
+    } else {
         // Remove files which shouldn't be part of the version anymore.
         old_files.retain(|ref remote| {
             !files.iter().any(|ref local| {
-                let mut abs_remote = PathBuf::from("/");
-                abs_remote.push(&remote.path);
-                let mut abs_local = PathBuf::from("/");
-                abs_local.push(&local.path);
-                abs_remote == abs_local
+                same_remote_path(&remote.path, &local.path)
             })
         });
         if old_files.len() > 0 {
             let _ = old_files.iter().fold((), |_, ref f| {
-                remove_file(&f, &client);
+                remove_file(backend, version_string, &f, retries);
             });
             let _ = writeln!(&mut std::io::stderr(), "");
         }
     }
+    retries.run("Finish uploads", || backend.finish_uploads(version_string))
+        .unwrap_or_else(|e| error_out(&e));
 
     if publish {
         let _ = writeln!(&mut std::io::stderr(),
         "\x1b[32mMark version as published...\x1b[0m");
+        let timeout = time::Duration::from_secs(
+            params.publish_timeout.unwrap_or(1800));
+        let poll_interval = time::Duration::from_secs(
+            params.publish_poll_interval.unwrap_or(10));
+        let start = time::Instant::now();
         let mut remaining_files = files.len();
         while remaining_files > 0 {
-            remaining_files = version
-                .publish_content(Some(-1), false, &client)
+            remaining_files = retries
+                .run("Publish version",
+                     || backend.publish(version_string))
                 .unwrap_or_else(|e| error_out(&e));
 
             if remaining_files > 0 {
-                thread::sleep(time::Duration::from_secs(10));
-            }
+                if start.elapsed() >= timeout {
+                    // Files listed without unpublished ones are the
+                    // published ones.
+                    let published = retries
+                        .run("List files",
+                             || backend.list_files(version_string, false))
+                        .unwrap_or_else(|e| error_out(&e));
+                    let unpublished = files.iter()
+                        .filter(|f| !published.iter()
+                                .any(|p| same_remote_path(&p.path, &f.path)))
+                        .collect::<Vec<_>>();
+                    error_out_with_files(
+                        &format!("Publishing timed out; {} file(s) \
+                                  still unpublished", remaining_files),
+                        &unpublished);
+                }
+                thread::sleep(poll_interval);
+            }
         }
     }
 
-    let show_in_download_list = match input.params.show_in_download_list {
+    let show_in_download_list = match params.show_in_download_list {
         Some(v) => v,
         None    => true,
     };
     if publish && show_in_download_list {
         let _ = writeln!(&mut std::io::stderr(),
         "\x1b[32mShow in download list...\x1b[0m");
+        let timeout = time::Duration::from_secs(
+            params.download_list_timeout.unwrap_or(1800));
+        let poll_interval = time::Duration::from_secs(
+            params.download_list_poll_interval.unwrap_or(10));
+        let start = time::Instant::now();
 
         // Even if the "publish version" request above returned there is
         // no more files to publish for the version, files may not be
         // published yet at the package level. Therefore we might get
         // a Bad Request error from the API (NotFound from the crate).
-        // If this happens, we retry 10 seconds later. But because this
-        // often fails we also sleep 10 seconds before sending the first
-        // attempt.
-        thread::sleep(time::Duration::from_secs(10));
-
-        let _ = files.iter()
-            .map(|ref f| {
-                loop {
-                    match f.show_in_download_list(true, &client) {
-                        Ok(_) => { break; }
-                        Err(BintrayError::Io(ref e))
-                        if e.kind() == io::ErrorKind::NotFound => {
-                            thread::sleep(time::Duration::from_secs(10));
+        // If this happens, we retry after the poll interval. But because
+        // this often fails we also wait before sending the first attempt.
+        thread::sleep(poll_interval);
+
+        for (i, f) in files.iter().enumerate() {
+            loop {
+                let what = format!("Show {} in download list",
+                                   f.path.display());
+                match retries.run(&what, || {
+                    backend.show_in_download_list(version_string, f)
+                }) {
+                    Ok(_) => { break; }
+                    Err(BintrayError::Io(ref e))
+                    if e.kind() == io::ErrorKind::NotFound => {
+                        if start.elapsed() >= timeout {
+                            let pending = files[i..].iter()
+                                .collect::<Vec<_>>();
+                            error_out_with_files(
+                                "Showing files in download list timed out",
+                                &pending);
                         }
-                        Err(e) => { error_out(&e); }
+                        thread::sleep(poll_interval);
                     }
+                    Err(e) => { error_out(&e); }
                 }
-            })
-            .collect::<Vec<_>>();
+            }
+        }
     }
 
     // Update version informations after files were uploaded and published.
-    let _ = version.get(false, &client);
-
-    // Print the result as JSON on stdout.
-    let result = get_out_result(&version);
-    match serde_json::to_string_pretty(&result) {
-        Ok(output) => { println!("{}", output); }
-        Err(e)     => { error_out(&BintrayError::Json(e)); }
+    let version = match retries.run("Get version",
+                                    || backend.get_version(version_string)) {
+        Ok(Some(updated)) => updated,
+        _                 => version,
     };
+
+    PublishedVersion {
+        version: version,
+        uploaded: uploaded,
+        removed: old_files.len(),
+    }
 }
 
-fn out_delete(client: BintrayClient, input: OutInput,
-              delete_package: bool)
+/// Prints the files which would be uploaded and removed. Returns their
+/// number.
+fn out_publish_dry_run(files: &[PathBuf],
+                       remote_path: &str,
+                       mut old_files: Vec<FileRecord>,
+                       keep_existing_files: bool)
+    -> (usize, usize)
 {
+    let files = files.iter()
+        .map(|filename| remote_content(filename, remote_path))
+        .collect::<Vec<FileRecord>>();
+    for file in files.iter() {
+        let replaced = old_files.iter()
+            .any(|old| same_remote_path(&old.path, &file.path));
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[32mWould upload file:\x1b[0m {}{}",
+            file.path.display(),
+            if replaced { " (replacing existing file)" } else { "" });
+    }
+    let _ = writeln!(&mut std::io::stderr(), "");
+
+    if keep_existing_files {
+        old_files.clear();
+    }
+    old_files.retain(|ref remote| {
+        !files.iter().any(|ref local| {
+            same_remote_path(&remote.path, &local.path)
+        })
+    });
+    for file in old_files.iter() {
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[34mWould remove file:\x1b[0m {}", file.path.display());
+    }
+    if !old_files.is_empty() {
+        let _ = writeln!(&mut std::io::stderr(), "");
+    }
+
+    (files.len(), old_files.len())
+}
+
+fn out_delete(backend: &dyn Backend,
+              input: OutInput,
+              delete_package: bool,
+              retries: &RetryPolicy)
+{
+    let version_props = input.params.version_props.as_ref();
+    let preview = version_props.and_then(|v| v.preview).unwrap_or(false);
+    let dry_run = input.params.dry_run.unwrap_or(false) || preview;
+    let special_version = String::from(
+        if dry_run { "<DRY-RUN>" } else { "<DELETED>" });
     let result = OutResult {
         version: CheckVersion {
-            version: String::from("<DELETED>"),
+            version: special_version.clone(),
             updated: None,
         },
         metadata: vec![],
     };
     let output = serde_json::to_string_pretty(&result)
-        .expect("Failed to convert <DELETED> version to JSON");
-
-    let mut package = Package::new(&input.source.subject,
-                                   &input.source.repository,
-                                   &input.source.package);
+        .expect("Failed to convert special version to JSON");
 
-    match package.exists(&client) {
+    match retries.run("Get package", || backend.package_exists()) {
         Ok(true) => {}
         Ok(false) => {
             println!("{}", output);
@@ -722,10 +1704,18 @@ fn out_delete(client: BintrayClient, input: OutInput,
     }
 
     if delete_package {
+        if dry_run {
+            let _ = writeln!(&mut std::io::stderr(),
+                "\x1b[33mWould remove package: {} \x1b[0m",
+                input.source.package);
+            println!("{}", output);
+            return;
+        }
+
         let _ = writeln!(&mut std::io::stderr(),
-            "\x1b[33mRemoving package: {} \x1b[0m", package.package);
+            "\x1b[33mRemoving package: {} \x1b[0m", input.source.package);
 
-        match package.delete(&client) {
+        match retries.run("Remove package", || backend.delete_package()) {
             Ok(warning) => log_bintray_warning(warning),
             Err(e)      => error_out(&e),
         }
@@ -734,55 +1724,281 @@ fn out_delete(client: BintrayClient, input: OutInput,
         return;
     }
 
-    let re_string = from_string_or_file(&input.params.version);
+    let re_string = match input.params.version {
+        VersionSource::FromRegex(regex) => regex,
+        VersionSource::FromFile(fileparams) => {
+            from_string_or_file(&StringOrFile::FromFile(fileparams))
+        }
+        VersionSource::FromPackage(_) => {
+            error_out(&io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "`version: {from_package: ...}` can't select versions \
+                     to delete; use a regular expression"));
+        }
+    };
     let _ = writeln!(&mut std::io::stderr(),
         "\x1b[32mVersion regex:\x1b[0m\n    {}\n", re_string);
 
     let re = Regex::new(&re_string)
         .unwrap_or_else(|e| error_out(&e));
 
-    let mut keep_last_n = match input.params.version_props.as_ref() {
-        Some(v) => v.keep_last_n.unwrap_or(0),
-        None    => 0,
+    let parse_duration_param = |name: &str, param: Option<&String>| {
+        param.map(|v| parse_duration(v)
+                  .unwrap_or_else(|e| error_out_with_message(name, e)))
     };
+    let policy = RetentionPolicy {
+        keep_last_n: version_props.and_then(|v| v.keep_last_n).unwrap_or(0),
+        keep_min: version_props.and_then(|v| v.keep_min).unwrap_or(0),
+        keep_newer_than: parse_duration_param(
+            "keep_newer_than",
+            version_props.and_then(|v| v.keep_newer_than.as_ref())),
+        delete_unpublished_older_than: parse_duration_param(
+            "delete_unpublished_older_than",
+            version_props.and_then(
+                |v| v.delete_unpublished_older_than.as_ref())),
+    };
+    let max_deletions = version_props.and_then(|v| v.max_deletions);
+
+    // Versions are sorted from the most recent to the oldest. Without
+    // an explicit `version_order`, they are sorted lexicographically.
+    let version_strings = retries
+        .run("List versions", || backend.list_version_names())
+        .unwrap_or_else(|e| error_out(&e));
+    let version_strings = sort_most_recent_first(version_strings,
+                                                 input.source.version_order);
+
+    // Compute the whole plan first: nothing is deleted if it goes over
+    // `max_deletions`.
+    let (kept, removed) = plan_version_deletion(
+        version_strings,
+        &re,
+        &policy,
+        |version_string| {
+            get_version_age(backend,
+                            version_string,
+                            policy.delete_unpublished_older_than.is_some(),
+                            retries)
+        });
+    for version_string in kept.iter() {
+        let _ = writeln!(&mut std::io::stderr(),
+            " Keeping version: {}", version_string);
+    }
+    for version_string in removed.iter() {
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[33m{} version: {} \x1b[0m",
+            if dry_run { "Would remove" } else { "Removing" },
+            version_string);
+    }
+    let _ = writeln!(&mut std::io::stderr(), "");
 
-    package.versions.sort();
-    package.versions.reverse();
-    for version_string in package.versions.iter() {
+    delete_versions(backend, &removed, max_deletions, dry_run, retries)
+        .unwrap_or_else(|e| error_out(&e));
+
+    let result = OutResult {
+        version: CheckVersion {
+            version: special_version,
+            updated: None,
+        },
+        metadata: vec![
+            OutMetadata {
+                name: String::from("Kept versions"),
+                value: kept.join(", "),
+            },
+            OutMetadata {
+                name: String::from(if dry_run { "Versions to remove" }
+                                   else { "Removed versions" }),
+                value: removed.join(", "),
+            },
+        ],
+    };
+    match serde_json::to_string_pretty(&result) {
+        Ok(output) => { println!("{}", output); }
+        Err(e)     => { error_out(&BintrayError::Json(e)); }
+    };
+}
+
+/// Removes the planned versions, unless it's a dry run. Fails before
+/// removing anything if there are more than `max_deletions` of them; a
+/// dry run only warns about it.
+fn delete_versions(backend: &dyn Backend,
+                   removed: &[String],
+                   max_deletions: Option<u64>,
+                   dry_run: bool,
+                   retries: &RetryPolicy)
+    -> Result<(), BintrayError>
+{
+    match max_deletions {
+        Some(max) if removed.len() as u64 > max => {
+            let error = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Refusing to remove {} versions: \
+                         `max_deletions` is set to {}",
+                         removed.len(), max));
+            if !dry_run {
+                return Err(BintrayError::from(error));
+            }
+            let _ = writeln!(&mut std::io::stderr(),
+                "\x1b[33m{}\x1b[0m\n", error);
+        }
+        _ => {}
+    }
+
+    if !dry_run {
+        for version_string in removed.iter() {
+            let warning = retries.run(
+                &format!("Remove version {}", version_string),
+                || backend.delete_version(version_string))?;
+            log_bintray_warning(warning);
+        }
+    }
+    Ok(())
+}
+
+/// Sorts versions listed from the oldest to the most recent following
+/// `version_order`, or lexicographically if it's unset, and returns them
+/// from the most recent to the oldest.
+fn sort_most_recent_first(mut version_strings: Vec<String>,
+                          version_order: Option<VersionOrder>)
+    -> Vec<String>
+{
+    match version_order {
+        Some(version_order) => {
+            version_strings.sort_by(|a, b| version_order.compare(a, b));
+        }
+        None => { version_strings.sort(); }
+    }
+    version_strings.reverse();
+    version_strings
+}
+
+/// Splits versions, sorted from the most recent to the oldest, between
+/// the ones to keep and the ones to remove.
+///
+/// `get_age` is only called for versions matching `re`, and only if the
+/// policy has an age-based rule.
+fn plan_version_deletion<F>(version_strings: Vec<String>,
+                            re: &Regex,
+                            policy: &RetentionPolicy,
+                            get_age: F)
+    -> (Vec<String>, Vec<String>)
+    where F: Fn(&str) -> VersionAge
+{
+    let needs_age = policy.keep_newer_than.is_some() ||
+        policy.delete_unpublished_older_than.is_some();
+
+    let mut kept = vec![];
+    let mut matching = vec![];
+    for version_string in version_strings {
         if re.is_match(&version_string) {
-            if keep_last_n > 0 {
-                let _ = writeln!(&mut std::io::stderr(),
-                    " Keeping version: {}", version_string);
+            matching.push(version_string);
+        } else {
+            kept.push(version_string);
+        }
+    }
 
-                keep_last_n = keep_last_n - 1;
-            } else {
-                let _ = writeln!(&mut std::io::stderr(),
-                    "\x1b[33mRemoving version: {} \x1b[0m", version_string);
-
-                let version = Version::new(&input.source.subject,
-                                           &input.source.repository,
-                                           &input.source.package,
-                                           &version_string);
-                match version.delete(&client) {
-                    Ok(warning) => {
-                        log_bintray_warning(warning);
-                    }
-                    Err(e) => { error_out(&e); }
-                }
+    // Decide for each matching version if it's kept, removed because it
+    // is an old unpublished version, or a candidate for removal.
+    let mut decisions = vec![];
+    let mut kept_count = 0;
+    for (i, version_string) in matching.iter().enumerate() {
+        let age = if needs_age {
+            Some(get_age(version_string))
+        } else {
+            None
+        };
+        let is_older_than = |limit: Option<time::Duration>| {
+            match (age.as_ref(), limit) {
+                (Some(age), Some(limit)) => age.age > limit,
+                _                        => false,
             }
+        };
+
+        let unpublished = age.as_ref().map_or(false, |a| a.unpublished);
+        let decision = if unpublished &&
+            is_older_than(policy.delete_unpublished_older_than) {
+            Some(false)
+        } else if (i as u64) < policy.keep_last_n ||
+            (policy.keep_newer_than.is_some() &&
+             !is_older_than(policy.keep_newer_than)) {
+            kept_count += 1;
+            Some(true)
         } else {
-            let _ = writeln!(&mut std::io::stderr(),
-                " Keeping version: {}", version_string);
+            None
+        };
+        decisions.push(decision);
+    }
+
+    // Keep the most recent candidates until at least `keep_min` versions
+    // are kept.
+    let mut removed = vec![];
+    for (version_string, decision) in matching.into_iter().zip(decisions) {
+        let keep = match decision {
+            Some(keep) => keep,
+            None if kept_count < policy.keep_min => {
+                kept_count += 1;
+                true
+            }
+            None => false,
+        };
+        if keep {
+            kept.push(version_string);
+        } else {
+            removed.push(version_string);
         }
     }
 
-    println!("{}", output);
+    (kept, removed)
 }
 
-fn find_files(filter: Option<StringVecOrFile>) -> Vec<PathBuf> {
+/// Fetches the version record to know how old it is and if it has
+/// unpublished files.
+fn get_version_age(backend: &dyn Backend,
+                   version_string: &str,
+                   check_unpublished: bool,
+                   retries: &RetryPolicy)
+    -> VersionAge
+{
+    let version = retries.run(&format!("Get version {}", version_string),
+                              || backend.get_version(version_string))
+        .unwrap_or_else(|e| error_out(&e))
+        .unwrap_or_else(|| error_out(&io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("The version {} doesn't exist", version_string))));
+
+    // Only `delete_unpublished_older_than` needs the files.
+    let unpublished = check_unpublished &&
+        retries.run("List files",
+                    || backend.has_unpublished_files(version_string))
+            .unwrap_or_else(|e| error_out(&e));
+
+    VersionAge {
+        age: version_age(&version, Utc::now()),
+        unpublished: unpublished,
+    }
+}
+
+/// Returns the time elapsed at `now` since the version was created. The
+/// update date isn't used: editing an old version doesn't make it new. A
+/// version without a creation date is considered new, so it is kept.
+fn version_age(version: &VersionRecord, now: DateTime<Utc>)
+    -> time::Duration
+{
+    version.created.as_ref()
+        .map(|date| {
+            date.parse::<DateTime<Utc>>()
+                .unwrap_or_else(|e| error_out_with_message(
+                    &format!("Invalid date \"{}\" of version {}",
+                             date, version.version),
+                    e))
+        })
+        .and_then(|date| now.signed_duration_since(date).to_std().ok())
+        .unwrap_or(time::Duration::from_secs(0))
+}
+
+fn find_files(filter: Option<&StringVecOrFile>) -> Vec<PathBuf> {
     let globs = filter.map_or(
         vec![String::from("**/*")],
-        |v| from_string_vec_or_file(&v));
+        from_string_vec_or_file);
 
     let mut result = vec![];
     result.extend(globs.iter()
@@ -805,28 +2021,64 @@ fn find_files(filter: Option<StringVecOrFile>) -> Vec<PathBuf> {
     result
 }
 
-fn determine_version(version: StringOrFile, files: &Vec<PathBuf>) -> String {
+fn determine_version(version: &VersionSource,
+                     consistency: VersionConsistency,
+                     files: &Vec<PathBuf>)
+    -> String
+{
+    let strict = consistency == VersionConsistency::Strict;
+
     // We first need to get the version string. It's available from one
     // of the following sources:
     //  * a regex against files which are part of the package/version;
-    //  * a text file.
-    let version_string = match version {
-        StringOrFile::FromString(regex) => {
-            let re = Regex::new(&regex)
+    //  * a text file;
+    //  * the metadata of a package which is part of the package/version.
+    // With a regex or a package, only the first matching file is
+    // considered, unless all of them must agree.
+    let version_string = match *version {
+        VersionSource::FromRegex(ref regex) => {
+            let re = Regex::new(regex)
                 .unwrap_or_else(|e| error_out(&e));
-            files.iter()
-                .fold(None,
-                      |acc, ref pathbuf| -> Option<String> {
-                          acc.or_else(|| capture_version(&re, &pathbuf))
-                      })
-                .unwrap_or_else(
-                    || error_out(
-                        &io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            "Failed to determine version from file names")))
+            let versions = files.iter()
+                .filter_map(|pathbuf| {
+                    capture_version(&re, pathbuf).map(|v| (pathbuf, v))
+                })
+                .take(if strict { usize::max_value() } else { 1 })
+                .collect::<Vec<(&PathBuf, String)>>();
+            if versions.is_empty() {
+                error_out(
+                    &io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Failed to determine version from file names"));
+            }
+            check_version_consistency(versions)
         }
-        StringOrFile::FromFile(_) => {
-            from_string_or_file(&version)
+        VersionSource::FromFile(ref fileparams) => {
+            from_string_or_file(&StringOrFile::FromFile(FromFile {
+                from_file: fileparams.from_file.clone(),
+            }))
+        }
+        VersionSource::FromPackage(ref params) => {
+            let pattern = Pattern::new(&params.from_package)
+                .unwrap_or_else(|e| error_out(&e));
+            let versions = files.iter()
+                .filter(|f| pattern.matches_path(f))
+                .take(if strict { usize::max_value() } else { 1 })
+                .map(|package| {
+                    let version = package_version::read_version(package)
+                        .unwrap_or_else(|e| error_out_with_filename(
+                                &package.to_string_lossy(), e));
+                    (package, version)
+                })
+                .collect::<Vec<(&PathBuf, String)>>();
+            if versions.is_empty() {
+                error_out(
+                    &io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("No file matches `from_package` {}",
+                                params.from_package)));
+            }
+            check_version_consistency(versions)
         }
     };
 
@@ -836,6 +2088,113 @@ fn determine_version(version: StringOrFile, files: &Vec<PathBuf>) -> String {
     version_string
 }
 
+/// Groups the files by the version they yield, from the lowest to the
+/// highest version. Files which don't yield a version are part of every
+/// group.
+fn group_files_by_version(version: &VersionSource,
+                          version_order: Option<VersionOrder>,
+                          files: Vec<PathBuf>)
+    -> Vec<(String, Vec<PathBuf>)>
+{
+    let versions = match *version {
+        VersionSource::FromRegex(ref regex) => {
+            let re = Regex::new(regex)
+                .unwrap_or_else(|e| error_out(&e));
+            files.iter()
+                .map(|pathbuf| capture_version(&re, pathbuf))
+                .collect::<Vec<Option<String>>>()
+        }
+        VersionSource::FromPackage(ref params) => {
+            let pattern = Pattern::new(&params.from_package)
+                .unwrap_or_else(|e| error_out(&e));
+            files.iter()
+                .map(|pathbuf| {
+                    if !pattern.matches_path(pathbuf) {
+                        return None;
+                    }
+                    let version = package_version::read_version(pathbuf)
+                        .unwrap_or_else(|e| error_out_with_filename(
+                                &pathbuf.to_string_lossy(), e));
+                    Some(version)
+                })
+                .collect()
+        }
+        VersionSource::FromFile(_) => {
+            error_out(&io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "`group_by_version` requires a regular expression or \
+                     `from_package` as `version`"));
+        }
+    };
+
+    let mut groups: Vec<(String, Vec<PathBuf>)> = vec![];
+    let mut common = vec![];
+    for (pathbuf, version) in files.into_iter().zip(versions) {
+        match version {
+            Some(version) => {
+                match groups.iter().position(|g| g.0 == version) {
+                    Some(i) => groups[i].1.push(pathbuf),
+                    None    => groups.push((version, vec![pathbuf])),
+                }
+            }
+            None => common.push(pathbuf),
+        }
+    }
+    if groups.is_empty() {
+        error_out(
+            &io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Failed to determine version from file names"));
+    }
+    for group in groups.iter_mut() {
+        group.1.extend(common.iter().cloned());
+    }
+
+    // The versions are not published yet, so they have no chronological
+    // order: without a `version_order` ordering version strings, they are
+    // sorted as semantic versions.
+    let version_order = match version_order {
+        Some(VersionOrder::Chronological) | None => VersionOrder::Semver,
+        Some(version_order)                      => version_order,
+    };
+    groups.sort_by(|a, b| version_order.compare(&a.0, &b.0));
+
+    let _ = writeln!(&mut std::io::stderr(),
+        "\x1b[32mVersions:\x1b[0m");
+    for group in groups.iter() {
+        let _ = writeln!(&mut std::io::stderr(),
+            "    {} ({} file(s))", group.0, group.1.len());
+    }
+    let _ = writeln!(&mut std::io::stderr(), "");
+
+    groups
+}
+
+/// Returns the version found in the files, if they all agree. Otherwise,
+/// exits with the version of each file.
+fn check_version_consistency(versions: Vec<(&PathBuf, String)>) -> String {
+    let version = versions[0].1.clone();
+    if versions.iter().all(|&(_, ref v)| *v == version) {
+        return version;
+    }
+
+    let width = versions.iter()
+        .map(|&(_, ref v)| v.len())
+        .max()
+        .unwrap_or(0)
+        .max("VERSION".len());
+    let _ = writeln!(&mut std::io::stderr(),
+        "\n\x1b[31mFiles disagree on the version:\x1b[0m");
+    let _ = writeln!(&mut std::io::stderr(),
+        "\x1b[31m    {:width$}  {}\x1b[0m", "VERSION", "FILE", width = width);
+    for &(file, ref v) in versions.iter() {
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[31m    {:width$}  {}\x1b[0m",
+            v, file.display(), width = width);
+    }
+    exit(1);
+}
+
 fn capture_version(re: &Regex, pathbuf: &PathBuf) -> Option<String> {
     pathbuf.to_str().and_then(
         |file|
@@ -848,122 +2207,75 @@ fn capture_version(re: &Regex, pathbuf: &PathBuf) -> Option<String> {
         })
 }
 
-fn update_package(props: Option<PackagePropsOutParams>,
+fn update_package(props: Option<&PackagePropsOutParams>,
                   source: &Source,
-                  client: &BintrayClient) -> Package
+                  dry_run: bool,
+                  backend: &dyn Backend,
+                  retries: &RetryPolicy)
 {
     // Create or update package properties with input params.
-    let mut package = Package::new(&source.subject,
-                                   &source.repository,
-                                   &source.package);
-
-    let exists = match package.exists(client) {
-        Ok(exists) => exists,
-        Err(e)     => error_out(&e),
+    let props = match props {
+        Some(props) => PackageProps {
+            desc: props.desc.as_ref().map(from_string_or_file),
+            labels: props.labels.as_ref().map(from_string_vec_or_file),
+            public_download_numbers: props.public_download_numbers,
+            public_stats: props.public_stats,
+            maturity: props.maturity.as_ref().map(from_string_or_file),
+
+            licenses: props.licenses.as_ref().map(from_string_vec_or_file),
+            custom_licenses: props.custom_licenses.as_ref()
+                .map(from_string_vec_or_file),
+
+            website_url: props.website_url.as_ref().map(from_string_or_file),
+            issue_tracker_url: props.issue_tracker_url.as_ref()
+                .map(from_string_or_file),
+            vcs_url: props.vcs_url.as_ref().map(from_string_or_file),
+            github_repo: props.github_repo.as_ref().map(from_string_or_file),
+            github_release_notes_file: props.github_release_notes_file
+                .as_ref()
+                .map(from_string_or_file),
+        },
+        None => PackageProps::default(),
     };
 
-    let initial_package = package.clone();
-
-    match props {
-        Some(props) => {
-            package.desc = props.desc
-                .map_or(package.desc, |v| Some(from_string_or_file(&v)));
-            package.labels = props.labels
-                .map_or(package.labels,
-                        |v| Some(from_string_vec_or_file(&v)))
-                .map(|mut v| { v.sort(); v });
-            package.public_download_numbers = props.public_download_numbers
-                .unwrap_or(package.public_download_numbers);
-            package.public_stats = props.public_stats
-                .unwrap_or(package.public_stats);
-            package.maturity = props.maturity
-                .map_or(
-                    package.maturity,
-                    |v| Some(PackageMaturity::from(from_string_or_file(&v))));
-
-            package.licenses = props.licenses
-                .map_or(package.licenses,
-                        |v| Some(from_string_vec_or_file(&v)))
-                .map(|mut v| { v.sort(); v });
-            package.custom_licenses = props.custom_licenses
-                .map_or(package.custom_licenses,
-                        |v| Some(from_string_vec_or_file(&v)))
-                .map(|mut v| { v.sort(); v });
-
-            package.website_url = props.website_url
-                .map_or(package.website_url,
-                        |v| Some(from_string_or_file(&v)));
-            package.issue_tracker_url = props.issue_tracker_url
-                .map_or(package.issue_tracker_url,
-                        |v| Some(from_string_or_file(&v)));
-            package.vcs_url = props.vcs_url
-                .map_or(package.vcs_url, |v| Some(from_string_or_file(&v)));
-            package.github_repo = props.github_repo
-                .map_or(package.github_repo,
-                        |v| Some(from_string_or_file(&v)));
-            package.github_release_notes_file = props.github_release_notes_file
-                .map_or(package.github_release_notes_file,
-                        |v| Some(from_string_or_file(&v)));
-        }
-        None => { }
-    }
-
-    if !exists || package != initial_package {
-        let error_out_closure = |e| -> Result<(), ()> { error_out(&e); };
-
-        let _ = match exists {
-            true  => {
-                let _ = writeln!(&mut std::io::stderr(),
-                "\x1b[32mUpdate package record:\x1b[0m {}", package);
-                package.update(client).or_else(error_out_closure)
-            }
-            false => {
-                let _ = writeln!(&mut std::io::stderr(),
-                "\x1b[32mCreate package record:\x1b[0m {}", package);
-                package.create(client).or_else(error_out_closure)
-            },
-        };
-
-        let _ = package.get(false, client);
-    } else {
-        let _ = writeln!(&mut std::io::stderr(),
-        "Package record {} up-to-date", package);
-    }
-
-    package
+    let change = retries.run(if dry_run { "Get package" }
+                             else { "Update package" },
+                             || backend.update_package(&props, dry_run))
+        .unwrap_or_else(|e| error_out(&e));
+    log_record_change("Package", &package_name(source), &change, dry_run);
 }
 
-fn update_version(props: Option<VersionPropsOutParams>,
+fn update_version(props: Option<&VersionPropsOutParams>,
                   source: &Source,
                   version_string: &str,
-                  client: &BintrayClient)
-    -> Version
+                  dry_run: bool,
+                  backend: &dyn Backend,
+                  retries: &RetryPolicy)
+    -> VersionRecord
 {
     // Create or update package properties with input params.
-    let mut version = Version::new(&source.subject,
-                                   &source.repository,
-                                   &source.package,
-                                   version_string);
-
-    let exists = match version.exists(client) {
-        Ok(exists) => exists,
-        Err(e)     => error_out(&e),
-    };
+    let existing = retries.run("Get version",
+                               || backend.get_version(version_string))
+        .unwrap_or_else(|e| error_out(&e));
+    let exists = existing.is_some();
 
-    let initial_version = version.clone();
+    let initial_version = existing
+        .unwrap_or_else(|| VersionRecord::new(version_string));
+    let mut version = initial_version.clone();
 
     match props {
         Some(props) => {
-            version.desc = props.desc
-                .map_or(version.desc, |v| Some(from_string_or_file(&v)));
-            version.released = props.released
-                .map_or(version.released, |v| Some(from_string_or_file(&v)));
+            version.desc = props.desc.as_ref()
+                .map_or(version.desc, |v| Some(from_string_or_file(v)));
+            version.released = props.released.as_ref()
+                .map_or(version.released, |v| Some(from_string_or_file(v)));
 
-            version.vcs_tag = props.vcs_tag
-                .map_or(version.vcs_tag, |v| Some(from_string_or_file(&v)));
+            version.vcs_tag = props.vcs_tag.as_ref()
+                .map_or(version.vcs_tag, |v| Some(from_string_or_file(v)));
             version.github_release_notes_file = props.github_release_notes_file
+                .as_ref()
                 .map_or(version.github_release_notes_file,
-                        |v| Some(from_string_or_file(&v)));
+                        |v| Some(from_string_or_file(v)));
             version.github_use_tag_release_notes =
                 props.github_use_tag_release_notes
                 .or(version.github_use_tag_release_notes);
@@ -971,78 +2283,400 @@ fn update_version(props: Option<VersionPropsOutParams>,
         None => { }
     }
 
-    if !exists || version != initial_version {
-        let error_out_closure = |e| -> Result<(), ()> { error_out(&e); };
+    let changes = changed_fields!(initial_version, version,
+                                  desc, released, vcs_tag,
+                                  github_release_notes_file,
+                                  github_use_tag_release_notes);
+    let change = match (exists, changes.is_empty()) {
+        (false, _)    => RecordChange::Created(changes),
+        (true, false) => RecordChange::Updated(changes),
+        (true, true)  => RecordChange::UpToDate,
+    };
 
-        let _ = match exists {
-            true  => {
-                let _ = writeln!(&mut std::io::stderr(),
-                "\x1b[32mCreate version record:\x1b[0m {}", version);
-                version.update(client).or_else(error_out_closure)
+    if !dry_run {
+        let result = match change {
+            RecordChange::Created(_) => {
+                retries.run_non_idempotent(
+                    "Create version", || backend.create_version(&version))
             }
-            false => {
-                let _ = writeln!(&mut std::io::stderr(),
-                "\x1b[32mCreate version record:\x1b[0m {}", version);
-                version.create(client).or_else(error_out_closure)
+            RecordChange::Updated(_) => {
+                retries.run("Update version",
+                            || backend.update_version(&version))
             }
+            RecordChange::UpToDate => Ok(()),
         };
-
-        let _ = version.get(false, client);
-    } else {
-        let _ = writeln!(&mut std::io::stderr(),
-        "Version record {} up-to-date", version);
+        result.unwrap_or_else(|e| error_out(&e));
+    }
+    log_record_change("Version",
+                      &format!("{}/{}", package_name(source), version_string),
+                      &change,
+                      dry_run);
+
+    if !dry_run && change != RecordChange::UpToDate {
+        match retries.run("Get version",
+                          || backend.get_version(version_string)) {
+            Ok(Some(updated)) => { version = updated; }
+            _                 => { }
+        }
     }
 
     version
 }
 
-fn upload_file<T: Borrow<str>>(filename: &PathBuf,
-                               remote_path: &str,
-                               publish: bool,
-                               override_: bool,
-                               gpg_passphrase: Option<&str>,
-                               debian_architecture: &[T],
-                               debian_distribution: &[T],
-                               debian_component: &[T],
-                               version: &Version,
-                               client: &BintrayClient) -> Content
+fn upload_file(backend: &dyn Backend,
+               version_string: &str,
+               filename: &PathBuf,
+               remote_path: &str,
+               options: &UploadOptions,
+               retries: &RetryPolicy) -> FileRecord
 {
-    let mut path = PathBuf::from(remote_path);
-    path.push(filename);
+    let file = remote_content(filename, remote_path);
 
-    let file = Content::new(&version.owner,
-                            &version.repository,
-                            &version.package,
-                            &version.version,
-                            &path);
+    // With `debian_architecture: auto`, each package is uploaded with the
+    // architecture from its control data, other files with none.
+    let architecture = if is_auto_architecture(options.debian_architecture) {
+        if filename.extension() == Some(OsStr::new("deb")) {
+            vec![deb_architecture(filename)]
+        } else {
+            vec![]
+        }
+    } else {
+        options.debian_architecture.to_vec()
+    };
+    let options = &UploadOptions {
+        debian_architecture: &architecture,
+        ..*options
+    };
 
     let _ = writeln!(&mut std::io::stderr(),
     "\x1b[32mUpload file:\x1b[0m {}", file.path.display());
 
-    match file.upload(filename, publish, override_, false,
-                      gpg_passphrase,
-                      debian_architecture,
-                      debian_distribution,
-                      debian_component,
-                      client) {
-        Ok(warning) => log_bintray_warning(warning),
-        Err(e)      => error_out(&e),
+    // Uploads may run concurrently: prefix messages with the file name
+    // so they can be told apart.
+    let what = format!("Upload {}", file.path.display());
+    let upload = || backend.upload_file(version_string,
+                                        filename,
+                                        &file,
+                                        options);
+    let result = if backend.is_upload_idempotent(options) {
+        retries.run(&what, upload)
+    } else {
+        retries.run_non_idempotent(&what, upload)
+    };
+    match result {
+        Ok(warning) => log_bintray_warning(
+            warning.map(|m| format!("{}: {}", file.path.display(), m))),
+        Err(e)      => error_out_with_filename(&file.path.to_string_lossy(), e),
     };
 
     file
 }
 
-fn remove_file(file: &Content, client: &BintrayClient) {
+fn is_auto_architecture(architectures: &[String]) -> bool {
+    architectures.len() == 1 && architectures[0] == "auto"
+}
+
+/// Returns the `Architecture` field of a `.deb` package.
+fn deb_architecture(filename: &Path) -> String {
+    let control = deb::read_control(filename)
+        .unwrap_or_else(|e| error_out_with_filename(
+                &filename.to_string_lossy(), e));
+    match control.architecture() {
+        Some(architecture) => String::from(architecture),
+        None => error_out_with_filename(
+            &filename.to_string_lossy(),
+            io::Error::new(io::ErrorKind::InvalidData,
+                           "no `Architecture` field in control data")),
+    }
+}
+
+/// Downloads the packages with `extension` stored in the version which
+/// this put keeps: without `keep_existing_files`, those which are not
+/// `uploaded` again are removed. Returns the directory they are
+/// downloaded to, with their local and remote paths.
+fn download_kept_packages(backend: &dyn Backend,
+                          version_string: &str,
+                          extension: &str,
+                          old_files: &[FileRecord],
+                          uploaded: &[FileRecord],
+                          keep_existing_files: bool,
+                          retries: &RetryPolicy)
+    -> (TempDir, Vec<(PathBuf, PathBuf)>)
+{
+    let dir = generated_files_dir("package");
+    if !keep_existing_files {
+        return (dir, vec![]);
+    }
+
+    let packages = old_files.iter()
+        .filter(|old| old.path.extension() == Some(OsStr::new(extension)))
+        .filter(|old| {
+            !uploaded.iter().any(|new| same_remote_path(&old.path, &new.path))
+        })
+        .map(|old| {
+            let _ = writeln!(&mut std::io::stderr(),
+                "\x1b[32mDownload stored package:\x1b[0m {}",
+                old.path.display());
+            let filename = dir.path().join(relative_path(&old.path));
+            let what = format!("Download {}", old.path.display());
+            filename.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .map_err(BintrayError::from)
+                .and_then(|_| retries.run(&what, || {
+                    backend.download_file(version_string, old, &filename)
+                }))
+                .unwrap_or_else(|e| error_out(&e));
+            verify_checksum(old, &filename)
+                .unwrap_or_else(|e| error_out_with_filename(
+                        &old.path.to_string_lossy(), e));
+            (filename, old.path.clone())
+        })
+        .collect::<Vec<(PathBuf, PathBuf)>>();
+    if !packages.is_empty() {
+        let _ = writeln!(&mut std::io::stderr(), "");
+    }
+    (dir, packages)
+}
+
+/// Generates the APT indexes listing the `.deb` packages, given by their
+/// local and remote paths, and uploads them to the root of the version.
+/// Returns the uploaded indexes.
+fn upload_apt_indexes(backend: &dyn Backend,
+                      version_string: &str,
+                      debs: &[(PathBuf, PathBuf)],
+                      options: &UploadOptions,
+                      source: &Source,
+                      keyring: Option<&Keyring>,
+                      retries: &RetryPolicy)
+    -> Vec<FileRecord>
+{
+    if options.debian_distribution.is_empty() ||
+        options.debian_component.is_empty() {
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[33mNo `debian_distribution` or `debian_component`: \
+             APT indexes not generated\x1b[0m\n");
+        return vec![];
+    }
+
+    let packages = debs.iter()
+        .map(|&(ref filename, ref path)| {
+            let control = deb::read_control(filename)
+                .unwrap_or_else(|e| error_out_with_filename(
+                        &path.to_string_lossy(), e));
+            AptPackage {
+                path: path.clone(),
+                filename: filename.clone(),
+                control: control,
+            }
+        })
+        .collect::<Vec<AptPackage>>();
+
+    let repository = AptRepository {
+        origin: &source.subject,
+        label: &source.repository,
+        distributions: options.debian_distribution,
+        components: options.debian_component,
+        architectures: if is_auto_architecture(options.debian_architecture) {
+            &[]
+        } else {
+            options.debian_architecture
+        },
+    };
+    let dir = generated_files_dir("index");
+    let mut indexes = repository.write_indexes(&packages, dir.path())
+        .unwrap_or_else(|e| error_out(&BintrayError::from(e)));
+
+    // Sign each `Release` file, both detached (`Release.gpg`) and inline
+    // (`InRelease`).
+    match keyring {
+        Some(keyring) => {
+            let releases = indexes.iter()
+                .filter(|index| {
+                    index.file_name() == Some(OsStr::new("Release"))
+                })
+                .cloned()
+                .collect::<Vec<PathBuf>>();
+            for release in releases {
+                let detached = release.with_file_name("Release.gpg");
+                let inline = release.with_file_name("InRelease");
+                let path = |p: &Path| dir.path().join(relative_path(p));
+                keyring.detach_sign(&path(&release), &path(&detached))
+                    .and_then(|_| keyring.clear_sign(&path(&release),
+                                                     &path(&inline)))
+                    .unwrap_or_else(|e| error_out_with_filename(
+                            &release.to_string_lossy(), e));
+                indexes.push(detached);
+                indexes.push(inline);
+            }
+        }
+        None => { }
+    }
+
+    upload_generated_files(backend, version_string, "index",
+                           dir.path(), &indexes, options, retries)
+}
+
+/// Generates the YUM repodata listing the `.rpm` files, given as local
+/// and remote paths, and uploads it at `depth` in the version. Returns
+/// the uploaded files.
+fn upload_yum_repodata(backend: &dyn Backend,
+                       version_string: &str,
+                       rpms: &[(PathBuf, PathBuf)],
+                       depth: usize,
+                       options: &UploadOptions,
+                       keyring: Option<&Keyring>,
+                       retries: &RetryPolicy)
+    -> Vec<FileRecord>
+{
+    let packages = rpms.iter()
+        .map(|&(ref filename, ref remote)| {
+            let header = rpm::read_header(filename)
+                .unwrap_or_else(|e| error_out_with_filename(
+                        &filename.to_string_lossy(), e));
+            YumPackage {
+                path: remote.clone(),
+                filename: filename.clone(),
+                header: header,
+            }
+        })
+        .collect::<Vec<YumPackage>>();
+
+    let dir = generated_files_dir("index");
+    let mut indexes = yum::write_repodata(&packages, depth, dir.path())
+        .unwrap_or_else(|e| error_out(&BintrayError::from(e)));
+    if indexes.is_empty() {
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[33mNo package at `yum_metadata_depth` {}: \
+             YUM repodata not generated\x1b[0m\n", depth);
+    }
+
+    match keyring {
+        Some(keyring) => {
+            let repomds = indexes.iter()
+                .filter(|index| {
+                    index.file_name() == Some(OsStr::new("repomd.xml"))
+                })
+                .cloned()
+                .collect::<Vec<PathBuf>>();
+            for repomd in repomds {
+                let signature = repomd.with_file_name("repomd.xml.asc");
+                let path = |p: &Path| dir.path().join(relative_path(p));
+                keyring.detach_sign(&path(&repomd), &path(&signature))
+                    .unwrap_or_else(|e| error_out_with_filename(
+                            &repomd.to_string_lossy(), e));
+                indexes.push(signature);
+            }
+        }
+        None => { }
+    }
+
+    upload_generated_files(backend, version_string, "index",
+                           dir.path(), &indexes, options, retries)
+}
+
+/// Writes the detached signatures of the local `files` to a generated
+/// files directory, at the path of the `.asc` file next to each remote
+/// file. Returns the directory and the signatures' paths.
+fn sign_files(files: &[PathBuf], remote_path: &str, keyring: &Keyring)
+    -> (TempDir, Vec<PathBuf>)
+{
+    let dir = generated_files_dir("signature");
+    let signatures = files.iter()
+        .filter(|f| f.extension() != Some(OsStr::new("asc")))
+        .map(|filename| {
+            let mut signature = remote_content(filename, remote_path).path
+                .into_os_string();
+            signature.push(".asc");
+            let signature = PathBuf::from(signature);
+
+            let output = dir.path().join(relative_path(&signature));
+            output.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| keyring.detach_sign(filename, &output))
+                .unwrap_or_else(|e| error_out_with_filename(
+                        &filename.to_string_lossy(), e));
+            signature
+        })
+        .collect();
+    (dir, signatures)
+}
+
+/// Creates a new local directory where files of `kind` (indexes,
+/// signatures...) are generated before they are uploaded. Each call
+/// gets its own directory, removed when it's dropped.
+fn generated_files_dir(kind: &str) -> TempDir {
+    TempDir::new(&format!("bintray-package-{}", kind))
+        .unwrap_or_else(|e| error_out(&BintrayError::from(e)))
+}
+
+/// Uploads the `files` of `kind` generated in `dir` to the same path in
+/// the version.
+fn upload_generated_files(backend: &dyn Backend,
+                          version_string: &str,
+                          kind: &str,
+                          dir: &Path,
+                          files: &[PathBuf],
+                          options: &UploadOptions,
+                          retries: &RetryPolicy)
+    -> Vec<FileRecord>
+{
+    let generated_options = UploadOptions {
+        publish: options.publish,
+        override_: true,
+        debian_architecture: &[],
+        debian_distribution: &[],
+        debian_component: &[],
+    };
+    let records = files.iter()
+        .map(|generated| {
+            let file = FileRecord::new(generated);
+            let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[32mUpload {}:\x1b[0m {}", kind, file.path.display());
+            let what = format!("Upload {}", file.path.display());
+            let upload = || backend.upload_file(
+                version_string,
+                &dir.join(relative_path(generated)),
+                &file,
+                &generated_options);
+            let result = if backend.is_upload_idempotent(&generated_options) {
+                retries.run(&what, upload)
+            } else {
+                retries.run_non_idempotent(&what, upload)
+            };
+            match result {
+                Ok(_)  => file,
+                Err(e) => error_out_with_filename(
+                    &generated.to_string_lossy(), e),
+            }
+        })
+        .collect();
+    let _ = writeln!(&mut std::io::stderr(), "");
+    records
+}
+
+fn remote_content(filename: &PathBuf, remote_path: &str) -> FileRecord {
+    let mut path = PathBuf::from(remote_path);
+    path.push(filename);
+
+    FileRecord::new(&path)
+}
+
+fn remove_file(backend: &dyn Backend,
+               version_string: &str,
+               file: &FileRecord,
+               retries: &RetryPolicy)
+{
     let _ = writeln!(&mut std::io::stderr(),
     "\x1b[34mRemove file:\x1b[0m {}", file.path.display());
 
-    match file.remove(client) {
+    let what = format!("Remove {}", file.path.display());
+    match retries.run(&what, || backend.remove_file(version_string, file)) {
         Ok(warning) => log_bintray_warning(warning),
         Err(e)      => error_out(&e),
     }
 }
 
-fn get_out_result(version: &Version) -> OutResult {
+fn get_out_result(version: &VersionRecord) -> OutResult {
     let mut metadata = vec![];
     version.released.as_ref().and_then(|release| {
         metadata.push(OutMetadata {
@@ -1060,13 +2694,191 @@ fn get_out_result(version: &Version) -> OutResult {
 // Internal functions.
 // -------------------------------------------------------------------
 
-fn version_for_concourse(version: &Version) -> CheckVersion {
+fn create_backend(source: &Source) -> Box<dyn Backend> {
+    match source.backend.unwrap_or(BackendType::Bintray) {
+        BackendType::Artifactory => {
+            let credentials = match (&source.username, &source.api_key) {
+                (&Some(ref username), &Some(ref api_key)) => {
+                    artifactory::Credentials::Basic(username.clone(),
+                                                    api_key.clone())
+                }
+                (&None, &Some(ref api_key)) => {
+                    artifactory::Credentials::Token(api_key.clone())
+                }
+                (_, &None) => artifactory::Credentials::Anonymous,
+            };
+            let backend = ArtifactoryBackend::new(&source.subject,
+                                                  credentials,
+                                                  &source.repository,
+                                                  &source.package);
+            Box::new(backend.unwrap_or_else(|e| error_out(&e)))
+        }
+        BackendType::Bintray => {
+            Box::new(BintrayBackend::new(
+                    required_source_field(&source.username,
+                                          "username", "bintray"),
+                    required_source_field(&source.api_key,
+                                          "api_key", "bintray"),
+                    &source.subject,
+                    &source.repository,
+                    &source.package,
+                    bintray_gpg_passphrase(source)))
+        }
+        BackendType::Filesystem => {
+            Box::new(FilesystemBackend::new(
+                    required_source_field(&source.root,
+                                          "root", "filesystem"),
+                    &source.subject,
+                    &source.repository,
+                    &source.package))
+        }
+        BackendType::GitHubReleases => {
+            let backend = GitHubBackend::new(
+                source.endpoint.as_ref().map(String::as_str),
+                source.api_key.as_ref().map(String::as_str),
+                &source.subject,
+                &source.repository);
+            Box::new(backend.unwrap_or_else(|e| error_out(&e)))
+        }
+        BackendType::S3 => {
+            let credentials = S3Credentials {
+                access_key_id: String::from(required_source_field(
+                        &source.access_key_id, "access_key_id", "s3")),
+                secret_access_key: String::from(required_source_field(
+                        &source.secret_access_key, "secret_access_key", "s3")),
+            };
+            let backend = S3Backend::new(
+                source.endpoint.as_ref().map(String::as_str),
+                source.region.as_ref().map_or("us-east-1", String::as_str),
+                required_source_field(&source.bucket, "bucket", "s3"),
+                credentials,
+                &source.subject,
+                &source.repository,
+                &source.package);
+            Box::new(backend.unwrap_or_else(|e| error_out(&e)))
+        }
+    }
+}
+
+/// Returns the retry policy of the source, built once per step and shared
+/// by all the calls to its backend(s).
+fn retry_policy(source: &Source) -> RetryPolicy {
+    source.retries.unwrap_or_default()
+}
+
+/// Returns the passphrase Bintray signs uploaded files with. Files signed
+/// locally with `gpg_private_key` are not signed by Bintray too: both
+/// signatures would be uploaded as the same `.asc` file.
+fn bintray_gpg_passphrase(source: &Source) -> Option<&str> {
+    match source.gpg_private_key {
+        Some(_) => None,
+        None    => source.gpg_passphrase.as_ref().map(String::as_str),
+    }
+}
+
+/// Returns a keyring holding the public keys used to verify signatures.
+fn trusted_keyring(keys: &StringVecOrFile) -> Keyring {
+    let keys = match *keys {
+        // A key file may hold several keys, imported at once.
+        StringVecOrFile::FromFile(ref fileparams) => {
+            let input = StringOrFile::FromFile(FromFile {
+                from_file: fileparams.from_file.clone(),
+            });
+            vec![from_string_or_file(&input)]
+        }
+        _ => from_string_vec_or_file(keys),
+    };
+
+    let keyring = Keyring::new(None)
+        .unwrap_or_else(|e| error_out(&BintrayError::from(e)));
+    for key in keys {
+        keyring.import(&key)
+            .unwrap_or_else(|e| error_out_with_message(
+                    "Invalid trusted_keys", e));
+    }
+    keyring
+}
+
+/// Returns a keyring holding the secret key used to sign files locally,
+/// unlocked with `gpg_passphrase`.
+fn signing_keyring(key: &StringOrFile, source: &Source) -> Keyring {
+    let keyring = Keyring::new(source.gpg_passphrase.as_ref()
+                               .map(String::as_str))
+        .unwrap_or_else(|e| error_out(&BintrayError::from(e)));
+    keyring.import(&from_string_or_file(key))
+        .unwrap_or_else(|e| error_out_with_message(
+                "Invalid gpg_private_key", e));
+    keyring
+}
+
+fn required_source_field<'a>(value: &'a Option<String>,
+                             name: &str,
+                             backend: &str)
+    -> &'a str
+{
+    match *value {
+        Some(ref value) => value,
+        None => error_out(&io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("`{}` is required by the {} backend",
+                        name, backend))),
+    }
+}
+
+fn package_name(source: &Source) -> String {
+    format!("{}/{}/{}", source.subject, source.repository, source.package)
+}
+
+fn version_for_concourse(version: &VersionRecord) -> CheckVersion {
     CheckVersion {
         version: version.version.clone(),
         updated: version.updated.clone(),
     }
 }
 
+fn same_remote_path(a: &Path, b: &Path) -> bool {
+    let mut abs_a = PathBuf::from("/");
+    abs_a.push(a);
+    let mut abs_b = PathBuf::from("/");
+    abs_b.push(b);
+    abs_a == abs_b
+}
+
+fn log_planned_changes(message: &str, changes: &[String]) {
+    let _ = writeln!(&mut std::io::stderr(),
+        "\x1b[33m{}\x1b[0m", message);
+    for change in changes {
+        let _ = writeln!(&mut std::io::stderr(), "    {}", change);
+    }
+}
+
+fn log_record_change(kind: &str,
+                     name: &str,
+                     change: &RecordChange,
+                     dry_run: bool)
+{
+    let (action, changes) = match *change {
+        RecordChange::Created(ref changes) => ("create", changes),
+        RecordChange::Updated(ref changes) => ("update", changes),
+        RecordChange::UpToDate => {
+            let _ = writeln!(&mut std::io::stderr(),
+                "{} record {} up-to-date", kind, name);
+            return;
+        }
+    };
+
+    let kind = kind.to_lowercase();
+    if dry_run {
+        log_planned_changes(
+            &format!("Would {} {} record: {}", action, kind, name),
+            changes);
+    } else {
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[32m{}{} {} record:\x1b[0m {}",
+            action[..1].to_uppercase(), &action[1..], kind, name);
+    }
+}
+
 fn log_bintray_warning(warning: Option<String>) {
     warning.and_then(|m| -> Option<()> {
         let _ =
@@ -1075,9 +2887,16 @@ fn log_bintray_warning(warning: Option<String>) {
     });
 }
 
+/// Removes the temporary directories still in use, which destructors
+/// won't, and exits with `code`.
+fn exit(code: i32) -> ! {
+    temp_dir::remove_all();
+    std::process::exit(code);
+}
+
 fn error_out<E: std::error::Error>(error: &E) -> ! {
     let _ = writeln!(&mut std::io::stderr(), "\n\x1b[31m{}\x1b[0m", error);
-    std::process::exit(1);
+    exit(1);
 }
 
 fn error_out_with_filename<E: std::error::Error>(filename: &str,
@@ -1086,7 +2905,27 @@ fn error_out_with_filename<E: std::error::Error>(filename: &str,
     let _ =
         writeln!(&mut std::io::stderr(), "\x1b[31m{}: {}\x1b[0m",
         filename, error);
-    std::process::exit(1);
+    exit(1);
+}
+
+/// Like `error_out()`, with a message giving the context of the error,
+/// e.g. the invalid param.
+fn error_out_with_message<E: std::error::Error>(message: &str,
+                                                error: E) -> !
+{
+    let _ =
+        writeln!(&mut std::io::stderr(), "\n\x1b[31m{}: {}\x1b[0m",
+        message, error);
+    exit(1);
+}
+
+fn error_out_with_files(message: &str, files: &[&FileRecord]) -> ! {
+    let _ = writeln!(&mut std::io::stderr(), "\n\x1b[31m{}:\x1b[0m", message);
+    for file in files {
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[31m    {}\x1b[0m", file.path.display());
+    }
+    exit(1);
 }
 
 fn from_string_or_file(input: &StringOrFile) -> String
@@ -1134,3 +2973,315 @@ fn from_string_vec_or_file(input: &StringVecOrFile) -> Vec<String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(versions: &[&str]) -> Vec<String> {
+        versions.iter().map(|v| String::from(*v)).collect()
+    }
+
+    fn keep_last_n(n: u64) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_last_n: n,
+            keep_min: 0,
+            keep_newer_than: None,
+            delete_unpublished_older_than: None,
+        }
+    }
+
+    fn no_age(version_string: &str) -> VersionAge {
+        panic!("Age of {} requested without an age-based rule",
+               version_string);
+    }
+
+    #[test]
+    fn keep_last_n_follows_version_order() {
+        // Listed chronologically: a 3.7.x hotfix was published last.
+        let versions = strings(&["3.7.9", "3.8.0-rc.1", "3.8.0-rc.2",
+                                 "3.8.0", "3.7.20"]);
+        let sorted = sort_most_recent_first(versions,
+                                            Some(VersionOrder::Semver));
+        assert_eq!(sorted, strings(&["3.8.0", "3.8.0-rc.2", "3.8.0-rc.1",
+                                     "3.7.20", "3.7.9"]));
+
+        let re = Regex::new(r"^3\.").unwrap();
+        let (kept, removed) = plan_version_deletion(sorted, &re,
+                                                    &keep_last_n(2), no_age);
+        assert_eq!(kept, strings(&["3.8.0", "3.8.0-rc.2"]));
+        assert_eq!(removed, strings(&["3.8.0-rc.1", "3.7.20", "3.7.9"]));
+    }
+
+    #[test]
+    fn keep_last_n_without_version_order_is_lexicographic() {
+        let versions = strings(&["3.7.9", "3.7.20", "3.8.0"]);
+        let sorted = sort_most_recent_first(versions, None);
+        assert_eq!(sorted, strings(&["3.8.0", "3.7.9", "3.7.20"]));
+
+        let re = Regex::new(r"^3\.7\.").unwrap();
+        let (kept, removed) = plan_version_deletion(sorted, &re,
+                                                    &keep_last_n(1), no_age);
+        assert_eq!(kept, strings(&["3.8.0", "3.7.9"]));
+        assert_eq!(removed, strings(&["3.7.20"]));
+    }
+
+    #[test]
+    fn keep_last_n_chronological_keeps_listing_order() {
+        let versions = strings(&["3.8.0", "3.7.20"]);
+        let sorted = sort_most_recent_first(
+            versions, Some(VersionOrder::Chronological));
+        assert_eq!(sorted, strings(&["3.7.20", "3.8.0"]));
+    }
+
+    #[test]
+    fn grouped_versions_default_to_semver_order() {
+        let files = vec![PathBuf::from("pkg-3.10.0.tar.gz"),
+                         PathBuf::from("pkg-3.9.0.tar.gz"),
+                         PathBuf::from("README.md")];
+        let version = VersionSource::FromRegex(
+            String::from(r"pkg-(?P<version>[0-9.]+)\.tar"));
+        for version_order in &[None, Some(VersionOrder::Chronological)] {
+            let groups = group_files_by_version(&version, *version_order,
+                                                files.clone());
+            assert_eq!(groups, vec![
+                (String::from("3.9.0"),
+                 vec![PathBuf::from("pkg-3.9.0.tar.gz"),
+                      PathBuf::from("README.md")]),
+                (String::from("3.10.0"),
+                 vec![PathBuf::from("pkg-3.10.0.tar.gz"),
+                      PathBuf::from("README.md")]),
+            ]);
+        }
+    }
+
+    #[test]
+    fn metadata_files_are_only_in_the_root_directory() {
+        for name in &["version", "./released", "vcs_tag", "metadata.json"] {
+            assert!(is_metadata_file(Path::new(name)), "{}", name);
+        }
+        for name in &["version.txt", "lib/version", "sub/metadata.json"] {
+            assert!(!is_metadata_file(Path::new(name)), "{}", name);
+        }
+    }
+
+    fn days(n: u64) -> time::Duration {
+        time::Duration::from_secs(n * 24 * 3600)
+    }
+
+    /// Ages of the versions used by the retention rule tests, from the
+    /// most recent to the oldest. 4.0 still has unpublished files.
+    fn fixed_age(version_string: &str) -> VersionAge {
+        let (age, unpublished) = match version_string {
+            "5.0" => (days(1), false),
+            "4.0" => (days(10), true),
+            "3.0" => (days(40), false),
+            "2.0" => (days(50), false),
+            _     => panic!("Unexpected version {}", version_string),
+        };
+        VersionAge {
+            age: age,
+            unpublished: unpublished,
+        }
+    }
+
+    #[test]
+    fn keep_newer_than_keeps_recent_versions() {
+        let policy = RetentionPolicy {
+            keep_newer_than: Some(days(30)),
+            ..keep_last_n(0)
+        };
+        let (kept, removed) = plan_version_deletion(
+            strings(&["5.0", "4.0", "3.0", "2.0"]),
+            &Regex::new(r"\.0$").unwrap(), &policy, fixed_age);
+        assert_eq!(kept, strings(&["5.0", "4.0"]));
+        assert_eq!(removed, strings(&["3.0", "2.0"]));
+    }
+
+    #[test]
+    fn keep_min_keeps_the_most_recent_old_versions() {
+        let policy = RetentionPolicy {
+            keep_min: 3,
+            keep_newer_than: Some(days(5)),
+            ..keep_last_n(0)
+        };
+        let (kept, removed) = plan_version_deletion(
+            strings(&["5.0", "4.0", "3.0", "2.0"]),
+            &Regex::new(r"\.0$").unwrap(), &policy, fixed_age);
+        assert_eq!(kept, strings(&["5.0", "4.0", "3.0"]));
+        assert_eq!(removed, strings(&["2.0"]));
+    }
+
+    #[test]
+    fn old_unpublished_versions_are_removed_despite_keep_min() {
+        let policy = RetentionPolicy {
+            keep_min: 3,
+            keep_newer_than: Some(days(30)),
+            delete_unpublished_older_than: Some(days(7)),
+            ..keep_last_n(1)
+        };
+        let (kept, removed) = plan_version_deletion(
+            strings(&["5.0", "4.0", "3.0", "2.0"]),
+            &Regex::new(r"\.0$").unwrap(), &policy, fixed_age);
+        assert_eq!(kept, strings(&["5.0", "3.0", "2.0"]));
+        assert_eq!(removed, strings(&["4.0"]));
+
+        // Recent unpublished versions follow the other rules.
+        let policy = RetentionPolicy {
+            delete_unpublished_older_than: Some(days(20)),
+            ..policy
+        };
+        let (kept, removed) = plan_version_deletion(
+            strings(&["5.0", "4.0", "3.0", "2.0"]),
+            &Regex::new(r"\.0$").unwrap(), &policy, fixed_age);
+        assert_eq!(kept, strings(&["5.0", "4.0", "3.0"]));
+        assert_eq!(removed, strings(&["2.0"]));
+    }
+
+    #[test]
+    fn version_age_is_counted_from_the_creation() {
+        let now = "2020-07-01T00:00:00.000Z".parse::<DateTime<Utc>>()
+            .unwrap();
+        let version = VersionRecord {
+            created: Some(String::from("2020-06-01T00:00:00.000Z")),
+            updated: Some(String::from("2020-06-30T00:00:00.000Z")),
+            ..VersionRecord::new("3.8.0")
+        };
+        assert_eq!(version_age(&version, now), days(30));
+
+        let version = VersionRecord::new("3.8.1");
+        assert_eq!(version_age(&version, now), days(0));
+    }
+
+    /// A filesystem backend in `root` with the given versions.
+    fn versions_backend(root: &Path, versions: &[&str]) -> FilesystemBackend {
+        fs::create_dir_all(root.join("team/generic")).unwrap();
+        let backend = FilesystemBackend::new(root, "team", "generic", "pkg");
+        backend.update_package(&PackageProps::default(), false).unwrap();
+        for version in versions {
+            backend.create_version(&VersionRecord::new(version)).unwrap();
+        }
+        backend
+    }
+
+    fn stored_versions(backend: &FilesystemBackend) -> Vec<String> {
+        let mut versions = backend.list_versions(None).unwrap()
+            .into_iter()
+            .map(|v| v.version)
+            .collect::<Vec<String>>();
+        versions.sort();
+        versions
+    }
+
+    #[test]
+    fn latest_version_is_filtered_and_sorted_like_check() {
+        let root = TempDir::new("bintray-package-test").unwrap();
+        let backend = versions_backend(root.path(),
+                                       &["3.10.0", "3.9.0", "4.0.0"]);
+        let retries = RetryPolicy::default();
+        let version_filter = VersionMatchers {
+            include: vec![VersionMatcher::Regex(Regex::new(r"^3\.").unwrap())],
+            exclude: vec![],
+        };
+
+        assert_eq!(latest_version(&backend, &version_filter,
+                                  VersionOrder::Semver, &retries),
+                   Some(String::from("3.10.0")));
+
+        let version_filter = VersionMatchers {
+            include: vec![VersionMatcher::Regex(Regex::new(r"^5\.").unwrap())],
+            exclude: vec![],
+        };
+        assert_eq!(latest_version(&backend, &version_filter,
+                                  VersionOrder::Semver, &retries),
+                   None);
+    }
+
+    #[test]
+    fn preview_removes_nothing() {
+        let root = TempDir::new("bintray-package-test").unwrap();
+        let backend = versions_backend(root.path(), &["1.0", "2.0"]);
+        let retries = RetryPolicy::default();
+
+        let removed = strings(&["1.0", "2.0"]);
+        assert!(delete_versions(&backend, &removed, None, true, &retries)
+                .is_ok());
+        // Going over `max_deletions` only warns in a preview.
+        assert!(delete_versions(&backend, &removed, Some(1), true, &retries)
+                .is_ok());
+        assert_eq!(stored_versions(&backend), strings(&["1.0", "2.0"]));
+    }
+
+    #[test]
+    fn max_deletions_fails_before_removing_anything() {
+        let root = TempDir::new("bintray-package-test").unwrap();
+        let backend = versions_backend(root.path(),
+                                       &["1.0", "2.0", "3.0"]);
+        let retries = RetryPolicy::default();
+
+        let removed = strings(&["1.0", "2.0"]);
+        assert!(delete_versions(&backend, &removed, Some(1), false, &retries)
+                .is_err());
+        assert_eq!(stored_versions(&backend),
+                   strings(&["1.0", "2.0", "3.0"]));
+
+        assert!(delete_versions(&backend, &removed, Some(2), false, &retries)
+                .is_ok());
+        assert_eq!(stored_versions(&backend), strings(&["3.0"]));
+    }
+
+    /// A filesystem backend storing version 1.0 with the given files.
+    fn stored_version(root: &Path, files: &[&str]) -> FilesystemBackend {
+        fs::create_dir_all(root.join("team/debian")).unwrap();
+        let backend = FilesystemBackend::new(root, "team", "debian", "pkg");
+        backend.update_package(&PackageProps::default(), false).unwrap();
+        backend.create_version(&VersionRecord::new("1.0")).unwrap();
+
+        let options = UploadOptions {
+            publish: true,
+            override_: true,
+            debian_architecture: &[],
+            debian_distribution: &[],
+            debian_component: &[],
+        };
+        for file in files {
+            let filename = root.join("upload");
+            fs::write(&filename, file.as_bytes()).unwrap();
+            backend.upload_file("1.0", &filename, &FileRecord::new(file),
+                                &options)
+                .unwrap();
+        }
+        backend
+    }
+
+    #[test]
+    fn kept_packages_are_downloaded_for_the_indexes() {
+        let root = TempDir::new("bintray-package-test").unwrap();
+        let backend = stored_version(root.path(),
+                                     &["pool/a.deb", "pool/b.deb",
+                                       "pool/c.rpm", "README"]);
+        let old_files = backend.list_files("1.0", true).unwrap();
+        let uploaded = vec![FileRecord::new("/pool/b.deb")];
+        let retries = RetryPolicy::default();
+
+        let (dir, packages) = download_kept_packages(
+            &backend, "1.0", "deb", &old_files, &uploaded, true, &retries);
+        assert_eq!(packages, vec![(dir.path().join("pool/a.deb"),
+                                   PathBuf::from("pool/a.deb"))]);
+        let mut content = String::new();
+        File::open(&packages[0].0).unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "pool/a.deb");
+
+        let (dir, packages) = download_kept_packages(
+            &backend, "1.0", "rpm", &old_files, &uploaded, true, &retries);
+        assert_eq!(packages, vec![(dir.path().join("pool/c.rpm"),
+                                   PathBuf::from("pool/c.rpm"))]);
+
+        // Without `keep_existing_files`, stored packages are removed.
+        let (_, packages) = download_kept_packages(
+            &backend, "1.0", "deb", &old_files, &uploaded, false, &retries);
+        assert!(packages.is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 extern crate bintray;
 extern crate clap;
+#[macro_use]
+extern crate concourse_bintray_resources;
 extern crate env_logger;
 #[macro_use]
 extern crate log;
@@ -11,6 +13,7 @@ use bintray::client::{BintrayClient, BintrayError};
 use bintray::repository::{self, Repository};
 use bintray::utils;
 use clap::{App, Arg};
+use concourse_bintray_resources::retry::RetryPolicy;
 use std::env;
 use std::ffi::OsStr;
 use std::fs::File;
@@ -27,6 +30,7 @@ struct Source {
     subject: String,
     repository: String,
     repository_type: repository::RepositoryType,
+    retries: Option<RetryPolicy>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -68,7 +72,9 @@ struct OutParams {
     gpg_sign_files: Option<bool>,
     gpg_use_owner_key: Option<bool>,
 
-    yum_metadata_depth: Option<u64>
+    yum_metadata_depth: Option<u64>,
+
+    dry_run: Option<bool>,
 }
 
 #[derive(Serialize)]
@@ -177,10 +183,12 @@ fn check() {
         Some(input.source.username),
         Some(input.source.api_key));
 
+    let retries = input.source.retries.unwrap_or_default();
+
     let mut repo = Repository::new(&input.source.subject,
                                    &input.source.repository);
 
-    match repo.get(&client) {
+    match retries.run("Get repository", || repo.get(&client)) {
         Ok(()) => { }
         Err(BintrayError::Io(ref e))
             if e.kind() == io::ErrorKind::NotFound => { }
@@ -222,14 +230,37 @@ fn in_() {
         Err(e) => { error_out(&BintrayError::Json(e)); }
     };
 
+    /* We use version "<DRY-RUN>" as a special version after a dry run
+     * in `out`: the repository may not exist. */
+    match input.version {
+        Some(ref version) if version.created == "<DRY-RUN>" => {
+            let _ = writeln!(&mut std::io::stderr(),
+                "Getting special version {} is a no-op; returning it as is",
+                version.created);
+
+            let result = OutResult {
+                version: CheckVersion { created: version.created.clone() },
+                metadata: vec![],
+            };
+            let output = serde_json::to_string_pretty(&result)
+                .expect("Failed to convert <DRY-RUN> version to JSON");
+
+            println!("{}", output);
+            return;
+        }
+        _ => {}
+    }
+
     let client = BintrayClient::new(
         Some(input.source.username),
         Some(input.source.api_key));
 
+    let retries = input.source.retries.unwrap_or_default();
+
     let mut repo = Repository::new(&input.source.subject,
                                    &input.source.repository);
 
-    match repo.get(&client) {
+    match retries.run("Get repository", || repo.get(&client)) {
         Ok(()) => { }
         Err(e) => { error_out(&e) }
     }
@@ -275,10 +306,13 @@ fn out() {
         Some(input.source.username),
         Some(input.source.api_key));
 
+    let retries = input.source.retries.unwrap_or_default();
+
     let mut repo = Repository::new(&input.source.subject,
                                    &input.source.repository);
 
-    let exists = match repo.exists(&client) {
+    let exists = match retries.run("Get repository",
+                                   || repo.exists(&client)) {
         Ok(exists) => exists,
         Err(e)     => error_out(&e),
     };
@@ -315,15 +349,45 @@ fn out() {
         repo.yum_metadata_depth = input.params.yum_metadata_depth;
     }
 
+    if input.params.dry_run.unwrap_or(false) {
+        let changes = changed_fields!(initial_repo, repo,
+                                      private, business_unit, desc, labels,
+                                      gpg_sign_metadata, gpg_sign_files,
+                                      gpg_use_owner_key, yum_metadata_depth);
+        let message = match (exists, changes.is_empty()) {
+            (false, _)    => format!("Would create repository: {}", repo),
+            (true, false) => format!("Would update repository: {}", repo),
+            (true, true)  => format!("Repository {} up-to-date", repo),
+        };
+        let _ = writeln!(&mut std::io::stderr(),
+            "\x1b[33m{}\x1b[0m", message);
+        for change in changes {
+            let _ = writeln!(&mut std::io::stderr(), "    {}", change);
+        }
+
+        let result = OutResult {
+            version: CheckVersion { created: String::from("<DRY-RUN>") },
+            metadata: vec![],
+        };
+        match serde_json::to_string_pretty(&result) {
+            Ok(output) => { println!("{}", output); }
+            Err(e)     => { error_out(&BintrayError::Json(e)); }
+        };
+        return;
+    }
+
     if !exists || repo != initial_repo {
         let error_out_closure = |e| -> Result<(), ()> { error_out(&e); };
 
         let _ = match exists {
-            true  => repo.update(&client).or_else(error_out_closure),
-            false => repo.create(&client).or_else(error_out_closure),
+            true  => retries.run("Update repository", || repo.update(&client))
+                .or_else(error_out_closure),
+            false => retries.run_non_idempotent("Create repository",
+                                                || repo.create(&client))
+                .or_else(error_out_closure),
         };
 
-        let _ = repo.get(&client);
+        let _ = retries.run("Get repository", || repo.get(&client));
     }
 
     // Print the result as JSON on stdout.
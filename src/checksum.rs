@@ -0,0 +1,63 @@
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Returns the SHA-1 of the file's content, as a lowercase hex string.
+pub fn sha1_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut hasher = Sha1::new();
+    read_file(path, |buf| hasher.update(buf))?;
+    Ok(to_hex(&hasher.digest().bytes()))
+}
+
+/// Returns the SHA-256 of the file's content, as a lowercase hex string.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut hasher = Sha256::default();
+    read_file(path, |buf| hasher.input(buf))?;
+    Ok(to_hex(&hasher.result()))
+}
+
+/// Returns the SHA-256 of `data`.
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::default();
+    hasher.input(data);
+    hasher.result().to_vec()
+}
+
+/// Computes an HMAC-SHA256 (RFC 2104), as used to sign S3 requests.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::default();
+    inner.input(&block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    inner.input(data);
+    let mut outer = Sha256::default();
+    outer.input(&block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.input(&inner.result());
+    outer.result().to_vec()
+}
+
+fn read_file<P, F>(path: P, mut f: F) -> io::Result<()>
+    where P: AsRef<Path>, F: FnMut(&[u8])
+{
+    let mut file = File::open(path)?;
+    let mut buf = [0; 65536];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(()),
+            n => f(&buf[..n]),
+        }
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,142 @@
+//! Reading the control data of Debian binary packages.
+//!
+//! A `.deb` file is an `ar` archive whose `control.tar` member (possibly
+//! compressed with gzip or xz) holds the `control` file describing the
+//! package.
+
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use tar::Archive;
+use xz2::read::XzDecoder;
+
+const AR_MAGIC: &'static [u8] = b"!<arch>\n";
+const AR_HEADER_LEN: usize = 60;
+
+/// The fields of a `control` file.
+#[derive(Clone, Debug)]
+pub struct Control {
+    paragraph: String,
+    fields: Vec<(String, String)>,
+}
+
+impl Control {
+    /// Parses a control paragraph. Continuation lines are kept in the
+    /// value of their field.
+    pub fn parse(paragraph: &str) -> Control {
+        let paragraph = paragraph.trim_matches('\n');
+        let mut fields: Vec<(String, String)> = vec![];
+        for line in paragraph.lines() {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                match fields.last_mut() {
+                    Some(&mut (_, ref mut value)) => {
+                        value.push('\n');
+                        value.push_str(line);
+                    }
+                    None => { }
+                }
+            } else if let Some(colon) = line.find(':') {
+                fields.push((String::from(line[..colon].trim()),
+                             String::from(line[colon + 1..].trim())));
+            }
+        }
+        Control {
+            paragraph: String::from(paragraph),
+            fields: fields,
+        }
+    }
+
+    /// Returns the value of a field. Field names are case-insensitive.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    pub fn package(&self) -> Option<&str> {
+        self.get("Package")
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.get("Version")
+    }
+
+    pub fn architecture(&self) -> Option<&str> {
+        self.get("Architecture")
+    }
+
+    /// The control file as found in the package, without trailing
+    /// newlines.
+    pub fn paragraph(&self) -> &str {
+        &self.paragraph
+    }
+}
+
+/// Reads the control file of a `.deb` package.
+pub fn read_control<P: AsRef<Path>>(path: P) -> io::Result<Control> {
+    let mut file = File::open(path)?;
+
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    if magic != AR_MAGIC {
+        return Err(invalid_data("not a Debian package"));
+    }
+
+    loop {
+        let mut header = [0; AR_HEADER_LEN];
+        match file.read_exact(&mut header) {
+            Ok(()) => { }
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(invalid_data("no control.tar member"));
+            }
+            Err(e) => return Err(e),
+        }
+        let name = String::from_utf8_lossy(&header[0..16])
+            .trim_end()
+            .trim_end_matches('/')
+            .to_string();
+        let size = String::from_utf8_lossy(&header[48..58])
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| invalid_data("invalid ar member size"))?;
+
+        if name.starts_with("control.tar") {
+            let member = BufReader::new((&mut file).take(size));
+            let control = match name.as_str() {
+                "control.tar"    => read_control_tar(member)?,
+                "control.tar.gz" => read_control_tar(GzDecoder::new(member)?)?,
+                "control.tar.xz" => read_control_tar(XzDecoder::new(member))?,
+                _ => {
+                    return Err(invalid_data(
+                            &format!("unsupported member {}", name)));
+                }
+            };
+            return Ok(Control::parse(&control));
+        }
+
+        // Members are aligned on two bytes.
+        file.seek(SeekFrom::Current((size + size % 2) as i64))?;
+    }
+}
+
+fn read_control_tar<R: Read>(reader: R) -> io::Result<String> {
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let is_control = {
+            let path = entry.path()?;
+            path == Path::new("control") || path == Path::new("./control")
+        };
+        if is_control {
+            let mut control = String::new();
+            entry.read_to_string(&mut control)?;
+            return Ok(control);
+        }
+    }
+    Err(invalid_data("no control file in control.tar"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
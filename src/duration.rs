@@ -0,0 +1,44 @@
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct DurationError {
+    input: String,
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "Invalid duration \"{}\": expected a number followed by \
+                s, m, h, d or w (e.g. \"30d\")",
+               self.input)
+    }
+}
+
+impl error::Error for DurationError {
+    fn description(&self) -> &str {
+        "invalid duration"
+    }
+}
+
+/// Parses a duration like `90s`, `15m`, `12h`, `30d` or `2w`.
+pub fn parse_duration(input: &str) -> Result<Duration, DurationError> {
+    let error = || DurationError { input: String::from(input) };
+
+    let input = input.trim();
+    let unit_pos = input.find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(&error)?;
+    let (number, unit) = input.split_at(unit_pos);
+    let number = number.parse::<u64>().map_err(|_| error())?;
+    let unit_secs = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _   => return Err(error()),
+    };
+
+    Ok(Duration::from_secs(number * unit_secs))
+}
@@ -0,0 +1,103 @@
+//! Signing files and verifying signatures with GnuPG.
+//!
+//! Keys are imported in a temporary home directory, so the user's keyring
+//! is never read nor modified. The `gpg` command must be in the `PATH`.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use temp_dir::TempDir;
+
+/// A temporary GnuPG keyring, removed when dropped.
+pub struct Keyring {
+    home: TempDir,
+    passphrase: Option<String>,
+}
+
+impl Keyring {
+    /// Creates an empty keyring. `passphrase` unlocks the secret keys
+    /// imported later, if they are protected.
+    pub fn new(passphrase: Option<&str>) -> io::Result<Keyring> {
+        let home = TempDir::new("bintray-package-gpg")?;
+
+        Ok(Keyring {
+            home: home,
+            passphrase: passphrase.map(String::from),
+        })
+    }
+
+    /// Imports the ASCII-armored `key`, public or secret.
+    pub fn import(&self, key: &str) -> io::Result<()> {
+        self.run(&["--import"], key.as_bytes())
+    }
+
+    /// Writes the ASCII-armored detached signature of `path` to `output`,
+    /// usually `path` followed by `.asc`.
+    pub fn detach_sign(&self, path: &Path, output: &Path) -> io::Result<()> {
+        self.sign("--detach-sign", path, output)
+    }
+
+    /// Writes `path` wrapped in a clear text signature to `output`, as
+    /// the `InRelease` file of an APT repository.
+    pub fn clear_sign(&self, path: &Path, output: &Path) -> io::Result<()> {
+        self.sign("--clearsign", path, output)
+    }
+
+    /// Checks the detached signature `signature` of `path` against the
+    /// imported keys.
+    pub fn verify(&self, path: &Path, signature: &Path) -> io::Result<()> {
+        self.run(&["--verify",
+                   &signature.to_string_lossy(), &path.to_string_lossy()],
+                 &[])
+    }
+
+    fn sign(&self, mode: &str, path: &Path, output: &Path) -> io::Result<()> {
+        let passphrase = self.passphrase.as_ref().map_or("", String::as_str);
+        self.run(&["--pinentry-mode", "loopback", "--passphrase-fd", "0",
+                   "--digest-algo", "SHA256", "--armor", "--yes",
+                   "--output", &output.to_string_lossy(),
+                   mode, &path.to_string_lossy()],
+                 passphrase.as_bytes())
+    }
+
+    fn run(&self, args: &[&str], input: &[u8]) -> io::Result<()> {
+        let mut child = Command::new("gpg")
+            .arg("--batch")
+            .arg("--no-tty")
+            .arg("--homedir")
+            .arg(self.home.path())
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // gpg may exit before reading its input: its own error is
+            // more useful than a broken pipe.
+            let _ = stdin.write_all(input);
+        }
+
+        let output = child.wait_with_output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            let message = String::from_utf8_lossy(&output.stderr);
+            Err(io::Error::new(io::ErrorKind::Other,
+                               format!("gpg failed: {}", message.trim())))
+        }
+    }
+}
+
+impl Drop for Keyring {
+    fn drop(&mut self) {
+        // Stop the agent started by gpg for this home directory, before
+        // the directory is removed.
+        let _ = Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(self.home.path())
+            .args(&["--kill", "gpg-agent"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
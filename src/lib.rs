@@ -0,0 +1,31 @@
+extern crate bintray;
+extern crate chrono;
+extern crate crossbeam;
+extern crate flate2;
+extern crate hyper;
+extern crate hyper_native_tls;
+extern crate regex;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate sha1;
+extern crate sha2;
+extern crate tar;
+extern crate xz2;
+
+#[macro_use] mod macros;
+
+pub mod apt;
+pub mod backend;
+pub mod checksum;
+pub mod deb;
+pub mod duration;
+pub mod gpg;
+pub mod package_version;
+pub mod parallel;
+pub mod retry;
+pub mod rpm;
+pub mod semver_range;
+pub mod temp_dir;
+pub mod version_order;
+pub mod yum;
@@ -0,0 +1,17 @@
+/// Lists the fields which differ between two records, as
+/// `field: old -> new` strings. Used to show what an `out` would change.
+#[macro_export]
+macro_rules! changed_fields {
+    ($old:expr, $new:expr, $($field:ident),+) => {{
+        let mut changes: Vec<String> = vec![];
+        $(
+            if $old.$field != $new.$field {
+                changes.push(format!("{}: {:?} -> {:?}",
+                                     stringify!($field),
+                                     $old.$field,
+                                     $new.$field));
+            }
+        )+
+        changes
+    }}
+}
@@ -0,0 +1,134 @@
+//! Reading the version of a package from its metadata.
+//!
+//! The version is the upstream version: the epoch and the packaging
+//! revision of `.deb` and `.rpm` packages are left out, so the same
+//! software packaged several ways gets the same version.
+
+use deb;
+use flate2::read::GzDecoder;
+use regex::Regex;
+use rpm;
+use serde_json;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use tar::Archive;
+
+#[derive(Deserialize)]
+struct PackageJson {
+    version: Option<String>,
+}
+
+/// Reads the version of the package `path`, depending on its name:
+///   * a `.deb` package: the `Version` field of its control data;
+///   * an `.rpm` package: the version in its header;
+///   * a `.tar.gz` or `.tgz` archive: the content of its `VERSION` file,
+///     at the root of the archive or of its top-level directory;
+///   * a `Cargo.toml` manifest: the version of its `[package]` section;
+///   * a `package.json` manifest: its `version` property.
+pub fn read_version<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let path = path.as_ref();
+    let name = path.file_name().and_then(OsStr::to_str).unwrap_or("");
+
+    let version = if name.ends_with(".deb") {
+        deb_version(path)?
+    } else if name.ends_with(".rpm") {
+        rpm::read_header(path)?.version()
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        tarball_version(path)?
+    } else if name == "Cargo.toml" {
+        cargo_version(path)?
+    } else if name == "package.json" {
+        npm_version(path)?
+    } else {
+        return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported package format; expected a .deb, .rpm, \
+                 .tar.gz, Cargo.toml or package.json file"));
+    };
+
+    if version.is_empty() {
+        Err(invalid_data("no version found"))
+    } else {
+        Ok(version)
+    }
+}
+
+/// Returns the upstream version of a `.deb` package, i.e. its `Version`
+/// field without the epoch and the Debian revision.
+fn deb_version(path: &Path) -> io::Result<String> {
+    let control = deb::read_control(path)?;
+    let version = control.version()
+        .ok_or_else(|| invalid_data("no `Version` field in control data"))?;
+
+    let version = match version.find(':') {
+        Some(colon) => &version[colon + 1..],
+        None        => version,
+    };
+    let version = match version.rfind('-') {
+        Some(dash) => &version[..dash],
+        None       => version,
+    };
+    Ok(String::from(version))
+}
+
+fn tarball_version(path: &Path) -> io::Result<String> {
+    let mut archive = Archive::new(GzDecoder::new(File::open(path)?)?);
+
+    // The least deep `VERSION` file wins.
+    let mut found: Option<(usize, String)> = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let depth = {
+            let path = entry.path()?;
+            if path.file_name() != Some(OsStr::new("VERSION")) {
+                continue;
+            }
+            path.components().count()
+        };
+        if depth > 2 || found.as_ref().map_or(false, |f| f.0 <= depth) {
+            continue;
+        }
+
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        found = Some((depth, String::from(content.trim())));
+    }
+
+    found.map(|f| f.1)
+        .ok_or_else(|| invalid_data("no `VERSION` file in the archive"))
+}
+
+fn cargo_version(path: &Path) -> io::Result<String> {
+    let mut content = String::new();
+    BufReader::new(File::open(path)?).read_to_string(&mut content)?;
+
+    let re = Regex::new(r#"^version\s*=\s*"([^"]+)""#).unwrap();
+    let mut in_package = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+        } else if in_package {
+            match re.captures(line) {
+                Some(caps) => return Ok(String::from(&caps[1])),
+                None       => { }
+            }
+        }
+    }
+
+    Err(invalid_data("no version in the `[package]` section"))
+}
+
+fn npm_version(path: &Path) -> io::Result<String> {
+    let package: PackageJson = serde_json::from_reader(
+        BufReader::new(File::open(path)?))
+        .map_err(|e| invalid_data(&e.to_string()))?;
+    package.version
+        .ok_or_else(|| invalid_data("no `version` property"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,48 @@
+use crossbeam;
+use std::sync::Mutex;
+
+/// Calls `f` on each item using up to `parallelism` threads and returns
+/// the results in the same order as `items`.
+///
+/// `init` is called once per thread to create a state passed to `f`,
+/// typically an API client.
+pub fn parallel_map<T, R, S, I, F>(items: Vec<T>,
+                                   parallelism: usize,
+                                   init: I,
+                                   f: F)
+    -> Vec<R>
+    where T: Send, R: Send, I: Fn() -> S + Sync, F: Fn(&S, T) -> R + Sync
+{
+    let count = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results = Mutex::new((0..count).map(|_| None).collect::<Vec<_>>());
+
+    crossbeam::scope(|scope| {
+        let workers = (0..parallelism.max(1).min(count))
+            .map(|_| {
+                scope.spawn(|| {
+                    let state = init();
+                    loop {
+                        let next = queue.lock().unwrap().next();
+                        match next {
+                            Some((i, item)) => {
+                                let result = f(&state, item);
+                                results.lock().unwrap()[i] = Some(result);
+                            }
+                            None => { break; }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for worker in workers {
+            worker.join();
+        }
+    });
+
+    results.into_inner().unwrap()
+        .into_iter()
+        .map(|r| r.expect("Worker finished without a result"))
+        .collect()
+}
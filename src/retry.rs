@@ -0,0 +1,172 @@
+use bintray::client::BintrayError;
+use std::io::{self, Write};
+use std::{thread, time};
+
+/// How API calls are retried after a transient error.
+///
+/// The delay between two attempts starts at `initial_delay` seconds and
+/// doubles after each attempt, up to `max_delay` seconds.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_initial_delay")]
+    pub initial_delay: u64,
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+}
+
+fn default_max_attempts() -> u32 { 5 }
+fn default_initial_delay() -> u64 { 1 }
+fn default_max_delay() -> u64 { 60 }
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: default_max_attempts(),
+            initial_delay: default_initial_delay(),
+            max_delay: default_max_delay(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Calls `f` until it succeeds, it returns a permanent error or
+    /// `max_attempts` is reached. `what` describes the operation in
+    /// log messages.
+    pub fn run<T, F>(&self, what: &str, f: F) -> Result<T, BintrayError>
+        where F: FnMut() -> Result<T, BintrayError>
+    {
+        self.run_while(what, f, is_transient)
+    }
+
+    /// Like `run()`, for a call which must not be applied twice, such as
+    /// a POST creating a record: it's only retried if the request wasn't
+    /// sent. After a timeout, the server may have applied it already and
+    /// a retry would fail with a spurious "already exists" error.
+    pub fn run_non_idempotent<T, F>(&self, what: &str, f: F)
+        -> Result<T, BintrayError>
+        where F: FnMut() -> Result<T, BintrayError>
+    {
+        self.run_while(what, f, is_unsent)
+    }
+
+    fn run_while<T, F>(&self,
+                       what: &str,
+                       mut f: F,
+                       is_retryable: fn(&BintrayError) -> bool)
+        -> Result<T, BintrayError>
+        where F: FnMut() -> Result<T, BintrayError>
+    {
+        let mut attempt = 1;
+        let mut delay = self.initial_delay;
+        loop {
+            match f() {
+                Err(ref e) if attempt < self.max_attempts &&
+                    is_retryable(e) => {
+                    let _ = writeln!(&mut io::stderr(),
+                        "\x1b[33m{} failed: {}; retrying in {}s ({}/{})\x1b[0m",
+                        what, e, delay, attempt, self.max_attempts);
+                    thread::sleep(time::Duration::from_secs(delay));
+
+                    attempt += 1;
+                    delay = (delay * 2).min(self.max_delay);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Tells if an error may go away by retrying the same request.
+///
+/// The Bintray client reports client errors (bad request, missing
+/// resource, authentication failures...) as I/O errors of a specific
+/// kind, and JSON errors are caused by our input: those are permanent.
+/// Everything else (server errors, rate limiting, connection resets,
+/// timeouts...) is considered transient.
+pub fn is_transient(error: &BintrayError) -> bool {
+    match *error {
+        BintrayError::Io(ref e) => match e.kind() {
+            io::ErrorKind::NotFound |
+            io::ErrorKind::PermissionDenied |
+            io::ErrorKind::AlreadyExists |
+            io::ErrorKind::InvalidInput |
+            io::ErrorKind::InvalidData => false,
+            _ => true,
+        },
+        BintrayError::Json(_) => false,
+        _ => true,
+    }
+}
+
+/// Tells if a request failed before it was sent, because the connection
+/// to the server couldn't be established. Backends keep the kind of
+/// these I/O errors; other request errors are reported as `Other`.
+pub fn is_unsent(error: &BintrayError) -> bool {
+    match *error {
+        BintrayError::Io(ref e) => match e.kind() {
+            io::ErrorKind::ConnectionRefused |
+            io::ErrorKind::NotConnected |
+            io::ErrorKind::AddrNotAvailable => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { max_attempts: 3, initial_delay: 0, max_delay: 0 }
+    }
+
+    fn io_error(kind: io::ErrorKind) -> BintrayError {
+        BintrayError::from(io::Error::new(kind, "test"))
+    }
+
+    /// Returns the number of calls made by `run` for a call always
+    /// failing with an I/O error of the given kind.
+    fn attempts<F>(kind: io::ErrorKind, run: F) -> u32
+        where F: Fn(&mut dyn FnMut() -> Result<(), BintrayError>)
+                    -> Result<(), BintrayError>
+    {
+        let mut calls = 0;
+        let _ = run(&mut || { calls += 1; Err(io_error(kind)) });
+        calls
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        let run = |f: &mut dyn FnMut() -> Result<(), BintrayError>| {
+            policy().run("test", f)
+        };
+        assert_eq!(attempts(io::ErrorKind::Other, &run), 3);
+        assert_eq!(attempts(io::ErrorKind::ConnectionRefused, &run), 3);
+        assert_eq!(attempts(io::ErrorKind::NotFound, &run), 1);
+    }
+
+    #[test]
+    fn non_idempotent_calls_are_retried_if_unsent() {
+        let run = |f: &mut dyn FnMut() -> Result<(), BintrayError>| {
+            policy().run_non_idempotent("test", f)
+        };
+        assert_eq!(attempts(io::ErrorKind::ConnectionRefused, &run), 3);
+        assert_eq!(attempts(io::ErrorKind::Other, &run), 1);
+        assert_eq!(attempts(io::ErrorKind::TimedOut, &run), 1);
+    }
+
+    #[test]
+    fn success_stops_retries() {
+        let mut calls = 0;
+        let result = policy().run("test", || {
+            calls += 1;
+            if calls < 2 { Err(io_error(io::ErrorKind::Other)) }
+            else { Ok(calls) }
+        });
+        assert_eq!(result.ok(), Some(2));
+    }
+}
@@ -0,0 +1,271 @@
+//! Reading the header of RPM packages.
+//!
+//! An RPM file starts with a 96-byte lead, followed by the signature
+//! header, padded to 8 bytes, then by the header describing the package.
+//! Both headers share the same structure: an index of tagged entries
+//! pointing into a data store.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+const LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const LEAD_LEN: usize = 96;
+const HEADER_MAGIC: [u8; 4] = [0x8e, 0xad, 0xe8, 0x01];
+
+pub const NAME: u32 = 1000;
+pub const VERSION: u32 = 1001;
+pub const RELEASE: u32 = 1002;
+pub const EPOCH: u32 = 1003;
+pub const SUMMARY: u32 = 1004;
+pub const DESCRIPTION: u32 = 1005;
+pub const BUILDTIME: u32 = 1006;
+pub const BUILDHOST: u32 = 1007;
+pub const SIZE: u32 = 1009;
+pub const VENDOR: u32 = 1011;
+pub const LICENSE: u32 = 1014;
+pub const PACKAGER: u32 = 1015;
+pub const GROUP: u32 = 1016;
+pub const URL: u32 = 1020;
+pub const ARCH: u32 = 1022;
+pub const OLDFILENAMES: u32 = 1027;
+pub const FILEMODES: u32 = 1030;
+pub const FILEFLAGS: u32 = 1037;
+pub const SOURCERPM: u32 = 1044;
+pub const ARCHIVESIZE: u32 = 1046;
+pub const PROVIDENAME: u32 = 1047;
+pub const REQUIREFLAGS: u32 = 1048;
+pub const REQUIRENAME: u32 = 1049;
+pub const REQUIREVERSION: u32 = 1050;
+pub const CONFLICTFLAGS: u32 = 1053;
+pub const CONFLICTNAME: u32 = 1054;
+pub const CONFLICTVERSION: u32 = 1055;
+pub const CHANGELOGTIME: u32 = 1080;
+pub const CHANGELOGNAME: u32 = 1081;
+pub const CHANGELOGTEXT: u32 = 1082;
+pub const OBSOLETENAME: u32 = 1090;
+pub const PROVIDEFLAGS: u32 = 1112;
+pub const PROVIDEVERSION: u32 = 1113;
+pub const OBSOLETEFLAGS: u32 = 1114;
+pub const OBSOLETEVERSION: u32 = 1115;
+pub const DIRINDEXES: u32 = 1116;
+pub const BASENAMES: u32 = 1117;
+pub const DIRNAMES: u32 = 1118;
+pub const LONGSIZE: u32 = 5009;
+
+const TYPE_CHAR: u32 = 1;
+const TYPE_INT8: u32 = 2;
+const TYPE_INT16: u32 = 3;
+const TYPE_INT32: u32 = 4;
+const TYPE_INT64: u32 = 5;
+const TYPE_STRING: u32 = 6;
+const TYPE_STRING_ARRAY: u32 = 8;
+const TYPE_I18NSTRING: u32 = 9;
+
+/// Flags of a file, in `FILEFLAGS`.
+pub const FILE_GHOST: u64 = 1 << 6;
+
+struct Entry {
+    tag: u32,
+    type_: u32,
+    offset: usize,
+    count: usize,
+}
+
+/// The header of a package.
+pub struct Header {
+    entries: Vec<Entry>,
+    data: Vec<u8>,
+    /// Offset of the header in the file.
+    pub start: u64,
+    /// Offset of the end of the header, i.e. of the payload.
+    pub end: u64,
+}
+
+/// A dependency, such as a `Requires:` or `Provides:` entry.
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    pub name: String,
+    pub flags: u64,
+    /// The version, as `[epoch:]version[-release]`. Empty if the
+    /// dependency isn't versioned.
+    pub version: String,
+}
+
+impl Header {
+    fn entry(&self, tag: u32) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+
+    /// Returns the values of a string, string array or I18N string entry.
+    /// Only the first translation of I18N strings is kept.
+    pub fn strings(&self, tag: u32) -> Vec<String> {
+        let entry = match self.entry(tag) {
+            Some(entry) => entry,
+            None        => return vec![],
+        };
+        let count = match entry.type_ {
+            TYPE_STRING | TYPE_I18NSTRING => 1,
+            TYPE_STRING_ARRAY             => entry.count,
+            _                             => return vec![],
+        };
+
+        let mut strings = vec![];
+        let mut offset = entry.offset;
+        for _ in 0..count {
+            let data = match self.data.get(offset..) {
+                Some(data) => data,
+                None       => break,
+            };
+            let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+            strings.push(String::from_utf8_lossy(&data[..len]).into_owned());
+            offset += len + 1;
+        }
+        strings
+    }
+
+    pub fn string(&self, tag: u32) -> Option<String> {
+        self.strings(tag).into_iter().next()
+    }
+
+    /// Returns the values of an integer entry.
+    pub fn integers(&self, tag: u32) -> Vec<u64> {
+        let entry = match self.entry(tag) {
+            Some(entry) => entry,
+            None        => return vec![],
+        };
+        let size = match entry.type_ {
+            TYPE_CHAR | TYPE_INT8 => 1,
+            TYPE_INT16            => 2,
+            TYPE_INT32            => 4,
+            TYPE_INT64            => 8,
+            _                     => return vec![],
+        };
+
+        (0..entry.count)
+            .filter_map(|i| {
+                let start = entry.offset + i * size;
+                self.data.get(start..start + size)
+            })
+            .map(|bytes| bytes.iter().fold(0, |n, b| n << 8 | *b as u64))
+            .collect()
+    }
+
+    pub fn integer(&self, tag: u32) -> Option<u64> {
+        self.integers(tag).into_iter().next()
+    }
+
+    pub fn name(&self) -> String {
+        self.string(NAME).unwrap_or_default()
+    }
+
+    pub fn version(&self) -> String {
+        self.string(VERSION).unwrap_or_default()
+    }
+
+    pub fn release(&self) -> String {
+        self.string(RELEASE).unwrap_or_default()
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.integer(EPOCH).unwrap_or(0)
+    }
+
+    pub fn arch(&self) -> String {
+        self.string(ARCH).unwrap_or_default()
+    }
+
+    /// Returns the paths of the files of the package.
+    pub fn files(&self) -> Vec<String> {
+        let basenames = self.strings(BASENAMES);
+        if basenames.is_empty() {
+            return self.strings(OLDFILENAMES);
+        }
+        let dirnames = self.strings(DIRNAMES);
+        let dirindexes = self.integers(DIRINDEXES);
+        basenames.iter()
+            .zip(dirindexes.iter())
+            .map(|(basename, index)| {
+                format!("{}{}",
+                        dirnames.get(*index as usize)
+                            .map_or("", String::as_str),
+                        basename)
+            })
+            .collect()
+    }
+
+    /// Returns the dependencies described by a name tag and the
+    /// associated flags and version tags.
+    pub fn dependencies(&self, name_tag: u32, flags_tag: u32, version_tag: u32)
+        -> Vec<Dependency>
+    {
+        let flags = self.integers(flags_tag);
+        let versions = self.strings(version_tag);
+        self.strings(name_tag)
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| Dependency {
+                name: name,
+                flags: flags.get(i).cloned().unwrap_or(0),
+                version: versions.get(i).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Reads the header of an `.rpm` package.
+pub fn read_header<P: AsRef<Path>>(path: P) -> io::Result<Header> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let mut lead = [0; LEAD_LEN];
+    file.read_exact(&mut lead)?;
+    if lead[..4] != LEAD_MAGIC {
+        return Err(invalid_data("not an RPM package"));
+    }
+
+    let signature = read_header_structure(&mut file, LEAD_LEN as u64)?;
+    let padding = (8 - signature.end % 8) % 8;
+    io::copy(&mut (&mut file).take(padding), &mut io::sink())?;
+    read_header_structure(&mut file, signature.end + padding)
+}
+
+fn read_header_structure<R: Read>(reader: &mut R, start: u64)
+    -> io::Result<Header>
+{
+    let mut intro = [0; 16];
+    reader.read_exact(&mut intro)?;
+    if intro[..4] != HEADER_MAGIC {
+        return Err(invalid_data("invalid RPM header"));
+    }
+    let index_len = be_u32(&intro[8..12]) as usize;
+    let data_len = be_u32(&intro[12..16]) as usize;
+
+    let mut index = vec![0; index_len * 16];
+    reader.read_exact(&mut index)?;
+    let entries = index.chunks(16)
+        .map(|e| Entry {
+            tag: be_u32(&e[0..4]),
+            type_: be_u32(&e[4..8]),
+            offset: be_u32(&e[8..12]) as usize,
+            count: be_u32(&e[12..16]) as usize,
+        })
+        .collect();
+
+    let mut data = vec![0; data_len];
+    reader.read_exact(&mut data)?;
+
+    Ok(Header {
+        entries: entries,
+        data: data,
+        start: start,
+        end: start + 16 + index.len() as u64 + data_len as u64,
+    })
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |n, b| n << 8 | *b as u32)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
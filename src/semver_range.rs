@@ -0,0 +1,308 @@
+use std::error;
+use std::fmt;
+use std::cmp::Ordering;
+use version_order::{compare_semver, parse_semver};
+
+/// A set of comparators, separated by commas, which a version must all
+/// satisfy: `>=3.8, <4`, `~3.8.1`, `^3`, `3.8.x`...
+///
+/// Like in Cargo and npm, a pre-release version only matches if one of
+/// the comparators is a pre-release of the same `major.minor.patch`.
+/// For example `>=3.8, <4` doesn't match `3.9.0-rc.1` or `4.0.0-beta.1`.
+#[derive(Debug, Clone)]
+pub struct SemverRange {
+    bounds: Vec<Bound>,
+}
+
+#[derive(Debug)]
+pub struct SemverRangeError {
+    range: String,
+    reason: String,
+}
+
+impl fmt::Display for SemverRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid semver range \"{}\": {}", self.range, self.reason)
+    }
+}
+
+impl error::Error for SemverRangeError {
+    fn description(&self) -> &str {
+        &self.reason
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone)]
+struct Bound {
+    op: Op,
+    version: String,
+}
+
+/// A version where trailing components may be missing or wildcards,
+/// like `3.8` or `3.8.x`.
+struct PartialVersion {
+    release: Vec<u64>,
+    pre: Option<String>,
+}
+
+impl PartialVersion {
+    fn parse(input: &str) -> Result<PartialVersion, String> {
+        let input = if input.starts_with('v') { &input[1..] } else { input };
+        let input = match input.find('+') {
+            Some(pos) => &input[..pos],
+            None      => input,
+        };
+        let (release, pre) = match input.find('-') {
+            Some(pos) => (&input[..pos], Some(String::from(&input[pos + 1..]))),
+            None      => (input, None),
+        };
+
+        let mut components = vec![];
+        let mut wildcard = false;
+        for component in release.split('.') {
+            match component {
+                "x" | "X" | "*" => { wildcard = true; }
+                _ if wildcard => {
+                    return Err(format!(
+                            "\"{}\" follows a wildcard", component));
+                }
+                _ => match component.parse::<u64>() {
+                    Ok(n)  => components.push(n),
+                    Err(_) => {
+                        return Err(format!(
+                                "\"{}\" is not a version number", input));
+                    }
+                }
+            }
+        }
+        if pre.is_some() && (wildcard || components.len() < 3) {
+            return Err(String::from(
+                    "a pre-release requires a complete version"));
+        }
+
+        Ok(PartialVersion { release: components, pre: pre })
+    }
+
+    fn is_complete(&self) -> bool {
+        self.release.len() >= 3
+    }
+
+    fn to_version(&self) -> String {
+        let mut release = self.release.clone();
+        while release.len() < 3 {
+            release.push(0);
+        }
+        let release = release.iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(".");
+        match self.pre {
+            Some(ref pre) => format!("{}-{}", release, pre),
+            None          => release,
+        }
+    }
+
+    /// Returns the smallest version greater than all versions matching
+    /// the first `len` components.
+    fn bump(&self, len: usize) -> String {
+        let mut release = self.release[..len].to_vec();
+        match release.last_mut() {
+            Some(last) => { *last += 1; }
+            None       => { }
+        }
+        PartialVersion { release: release, pre: None }.to_version()
+    }
+}
+
+impl SemverRange {
+    pub fn parse(range: &str) -> Result<SemverRange, SemverRangeError> {
+        let error = |reason: String| SemverRangeError {
+            range: String::from(range),
+            reason: reason,
+        };
+
+        let mut bounds = vec![];
+        for comparator in range.split(',') {
+            let comparator = comparator.trim();
+            if comparator.is_empty() {
+                return Err(error(String::from("empty comparator")));
+            }
+
+            let (op, version) = ["^", "~", ">=", "<=", ">", "<", "="].iter()
+                .find(|op| comparator.starts_with(*op))
+                .map_or(("", comparator),
+                        |op| (*op, comparator[op.len()..].trim()));
+            let partial = PartialVersion::parse(version).map_err(&error)?;
+            let len = partial.release.len();
+
+            match op {
+                ">=" => {
+                    bounds.push(Bound::new(Op::Ge, partial.to_version()));
+                }
+                ">" if partial.is_complete() => {
+                    bounds.push(Bound::new(Op::Gt, partial.to_version()));
+                }
+                ">" => {
+                    bounds.push(Bound::new(Op::Ge, partial.bump(len)));
+                }
+                "<" => {
+                    bounds.push(Bound::new(Op::Lt, partial.to_version()));
+                }
+                "<=" if partial.is_complete() => {
+                    bounds.push(Bound::new(Op::Le, partial.to_version()));
+                }
+                "<=" => {
+                    bounds.push(Bound::new(Op::Lt, partial.bump(len)));
+                }
+                "~" => {
+                    bounds.push(Bound::new(Op::Ge, partial.to_version()));
+                    bounds.push(Bound::new(Op::Lt, partial.bump(len.min(2))));
+                }
+                "^" => {
+                    // Bump the first non-zero component, or the last
+                    // specified one if they are all zero.
+                    let upper = partial.release.iter()
+                        .position(|n| *n != 0)
+                        .map_or(len, |pos| pos + 1);
+                    bounds.push(Bound::new(Op::Ge, partial.to_version()));
+                    bounds.push(Bound::new(Op::Lt, partial.bump(upper)));
+                }
+                _ if len == 0 => {
+                    // `*` matches all versions.
+                }
+                _ if partial.is_complete() => {
+                    bounds.push(Bound::new(Op::Eq, partial.to_version()));
+                }
+                _ => {
+                    bounds.push(Bound::new(Op::Ge, partial.to_version()));
+                    bounds.push(Bound::new(Op::Lt, partial.bump(len)));
+                }
+            }
+        }
+
+        Ok(SemverRange { bounds: bounds })
+    }
+
+    pub fn matches(&self, version: &str) -> bool {
+        let parsed = match parse_semver(version) {
+            Some(parsed) => parsed,
+            None         => return false,
+        };
+
+        if !parsed.pre.is_empty() {
+            let allowed = self.bounds.iter()
+                .filter_map(|b| parse_semver(&b.version))
+                .any(|b| !b.pre.is_empty() &&
+                     same_release(&b.release, &parsed.release));
+            if !allowed {
+                return false;
+            }
+        }
+
+        self.bounds.iter().all(|b| b.matches(version))
+    }
+}
+
+impl Bound {
+    fn new(op: Op, version: String) -> Bound {
+        Bound { op: op, version: version }
+    }
+
+    fn matches(&self, version: &str) -> bool {
+        let ordering = compare_semver(version, &self.version);
+        match self.op {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+        }
+    }
+}
+
+fn same_release(a: &[u64], b: &[u64]) -> bool {
+    let len = a.len().max(b.len());
+    (0..len).all(|i| a.get(i).unwrap_or(&0) == b.get(i).unwrap_or(&0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_matches(range: &str, matching: &[&str], not_matching: &[&str]) {
+        let parsed = SemverRange::parse(range).unwrap();
+        for version in matching {
+            assert!(parsed.matches(version), "{} matches {}", version, range);
+        }
+        for version in not_matching {
+            assert!(!parsed.matches(version),
+                    "{} doesn't match {}", version, range);
+        }
+    }
+
+    #[test]
+    fn comparator_list() {
+        assert_matches(">=3.8, <4",
+                       &["3.8.0", "3.8.10", "3.9.0", "3.99.99"],
+                       &["3.7.20", "4.0.0", "4.1.0"]);
+        assert_matches(">3.8, <=3.9",
+                       &["3.9.0", "3.9.10"],
+                       &["3.8.0", "3.8.10", "3.10.0"]);
+        assert_matches("=3.8.1", &["3.8.1", "v3.8.1"], &["3.8.0", "3.8.2"]);
+    }
+
+    #[test]
+    fn x_ranges() {
+        assert_matches("3.8.x", &["3.8.0", "3.8.10"], &["3.7.20", "3.9.0"]);
+        assert_matches("3.8.*", &["3.8.0", "3.8.10"], &["3.9.0"]);
+        assert_matches("3.8", &["3.8.0", "3.8.10"], &["3.9.0"]);
+        assert_matches("3.x", &["3.0.0", "3.8.10"], &["2.9.9", "4.0.0"]);
+        assert_matches("*", &["0.0.1", "3.8.0"], &["3.8.0-rc.1", "latest"]);
+    }
+
+    #[test]
+    fn tilde_ranges() {
+        assert_matches("~3.8", &["3.8.0", "3.8.10"], &["3.7.20", "3.9.0"]);
+        assert_matches("~3.8.1", &["3.8.1", "3.8.10"], &["3.8.0", "3.9.0"]);
+        assert_matches("~3", &["3.0.0", "3.9.0"], &["4.0.0"]);
+    }
+
+    #[test]
+    fn caret_ranges() {
+        assert_matches("^3.8.1", &["3.8.1", "3.9.0"], &["3.8.0", "4.0.0"]);
+        assert_matches("^0.2.3", &["0.2.3", "0.2.9"], &["0.3.0"]);
+        assert_matches("^0.0.3", &["0.0.3"], &["0.0.4"]);
+    }
+
+    #[test]
+    fn pre_releases_need_a_pre_release_comparator() {
+        assert_matches(">=3.8, <4",
+                       &[],
+                       &["3.9.0-rc.1", "4.0.0-beta.1", "3.8.0-rc.1"]);
+        assert_matches(">=3.8.0-rc.1, <4",
+                       &["3.8.0-rc.1", "3.8.0-rc.2", "3.8.0"],
+                       &["3.8.0-beta.1", "3.9.0-rc.1"]);
+        assert_matches("~3.8.0-rc.2",
+                       &["3.8.0-rc.2", "3.8.0-rc.10", "3.8.5"],
+                       &["3.8.0-rc.1", "3.8.1-rc.1"]);
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        for range in &["", ">=3.8,", "3.x.1", "foo", ">=3.8-rc.1",
+                       "3.8.x-rc.1"] {
+            assert!(SemverRange::parse(range).is_err(), "{} is invalid",
+                    range);
+        }
+        assert!(!SemverRange::parse(">=3.8").unwrap().matches("latest"));
+    }
+}
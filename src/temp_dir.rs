@@ -0,0 +1,103 @@
+//! Temporary directories with a unique name, removed when dropped.
+//!
+//! `process::exit()` doesn't run destructors: a program exiting early
+//! calls `remove_all()` first, so the directories still in use are
+//! removed too.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the directories created by this process.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The directories created and not removed yet.
+static LIVE_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// A directory in the system's temporary directory, only accessible to
+/// the current user.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates a new directory, named after `prefix`, the process id and
+    /// a counter so each call gets its own directory.
+    pub fn new(prefix: &str) -> io::Result<TempDir> {
+        loop {
+            let path = env::temp_dir().join(
+                format!("{}-{}-{}",
+                        prefix,
+                        process::id(),
+                        COUNTER.fetch_add(1, Ordering::SeqCst)));
+            match create_private_dir(&path) {
+                Ok(()) => {
+                    live_dirs().push(path.clone());
+                    return Ok(TempDir { path: path });
+                }
+                // Left by a previous process with the same id.
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => { }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+        live_dirs().retain(|p| *p != self.path);
+    }
+}
+
+/// Removes the directories which are not dropped yet. Called before
+/// exiting with `process::exit()`.
+pub fn remove_all() {
+    for path in live_dirs().drain(..) {
+        let _ = fs::remove_dir_all(path);
+    }
+}
+
+fn live_dirs() -> ::std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    // A thread panicking while holding the lock doesn't make the list
+    // invalid.
+    LIVE_DIRS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new().mode(0o700).create(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_directory_is_unique_and_removed() {
+        let first = TempDir::new("bintray-package-test").unwrap();
+        let second = TempDir::new("bintray-package-test").unwrap();
+        assert!(first.path() != second.path());
+        assert!(first.path().is_dir() && second.path().is_dir());
+
+        let path = PathBuf::from(first.path());
+        fs::write(path.join("file"), b"content").unwrap();
+        drop(first);
+        assert!(!path.exists());
+        assert!(second.path().is_dir());
+    }
+}
@@ -0,0 +1,404 @@
+use std::cmp::Ordering;
+
+/// How versions of a package are ordered from oldest to most recent.
+///
+/// `Chronological` keeps the order returned by Bintray, the other
+/// variants sort version strings following the rules of the named
+/// versioning scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum VersionOrder {
+    #[serde(rename = "chronological")]
+    Chronological,
+    #[serde(rename = "semver")]
+    Semver,
+    #[serde(rename = "debian")]
+    Debian,
+    #[serde(rename = "rpm")]
+    Rpm,
+}
+
+impl VersionOrder {
+    /// Compares two version strings. With `Chronological`, all versions
+    /// compare equal so a stable sort keeps the original order.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match *self {
+            VersionOrder::Chronological => Ordering::Equal,
+            VersionOrder::Semver        => compare_semver(a, b),
+            VersionOrder::Debian        => compare_debian(a, b),
+            VersionOrder::Rpm           => compare_rpm(a, b),
+        }
+    }
+}
+
+// -------------------------------------------------------------------
+// Semantic versioning.
+// -------------------------------------------------------------------
+
+pub(crate) struct SemVer<'a> {
+    pub release: Vec<u64>,
+    pub pre: Vec<&'a str>,
+}
+
+/// Parses `[v]X[.Y[.Z...]][-pre][+build]`. Missing release components
+/// are considered to be 0 when comparing, so `3.8` equals `3.8.0`.
+pub(crate) fn parse_semver<'a>(version: &'a str) -> Option<SemVer<'a>> {
+    let version = if version.starts_with('v') {
+        &version[1..]
+    } else {
+        version
+    };
+    let version = match version.find('+') {
+        Some(pos) => &version[..pos],
+        None      => version,
+    };
+    let (release, pre) = match version.find('-') {
+        Some(pos) => (&version[..pos], Some(&version[pos + 1..])),
+        None      => (version, None),
+    };
+
+    let mut components = vec![];
+    for component in release.split('.') {
+        match component.parse::<u64>() {
+            Ok(n)  => components.push(n),
+            Err(_) => return None,
+        }
+    }
+
+    let pre = match pre {
+        Some(pre) if pre.is_empty() => return None,
+        Some(pre)                   => pre.split('.').collect(),
+        None                        => vec![],
+    };
+
+    Some(SemVer { release: components, pre: pre })
+}
+
+fn compare_semver_identifiers(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b))   => a.cmp(&b),
+        (Ok(_), Err(_))  => Ordering::Less,
+        (Err(_), Ok(_))  => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Compares two versions following the Semantic Versioning precedence
+/// rules: `3.8.0-beta.2 < 3.8.0-rc.1 < 3.8.0 < 3.8.1`.
+///
+/// Versions which can't be parsed sort before all valid versions and
+/// are compared lexicographically between themselves.
+pub fn compare_semver(a: &str, b: &str) -> Ordering {
+    let (a_ver, b_ver) = match (parse_semver(a), parse_semver(b)) {
+        (Some(a), Some(b)) => (a, b),
+        (Some(_), None)    => return Ordering::Greater,
+        (None, Some(_))    => return Ordering::Less,
+        (None, None)       => return a.cmp(b),
+    };
+
+    let len = a_ver.release.len().max(b_ver.release.len());
+    for i in 0..len {
+        let a_n = a_ver.release.get(i).cloned().unwrap_or(0);
+        let b_n = b_ver.release.get(i).cloned().unwrap_or(0);
+        match a_n.cmp(&b_n) {
+            Ordering::Equal => { }
+            ordering        => return ordering,
+        }
+    }
+
+    // A pre-release version has a lower precedence than the associated
+    // normal version.
+    match (a_ver.pre.is_empty(), b_ver.pre.is_empty()) {
+        (true, true)   => return Ordering::Equal,
+        (true, false)  => return Ordering::Greater,
+        (false, true)  => return Ordering::Less,
+        (false, false) => { }
+    }
+
+    for (a_id, b_id) in a_ver.pre.iter().zip(b_ver.pre.iter()) {
+        match compare_semver_identifiers(a_id, b_id) {
+            Ordering::Equal => { }
+            ordering        => return ordering,
+        }
+    }
+    a_ver.pre.len().cmp(&b_ver.pre.len())
+}
+
+// -------------------------------------------------------------------
+// Debian versions (same algorithm as dpkg).
+// -------------------------------------------------------------------
+
+fn split_debian(version: &str) -> (u64, &str, &str) {
+    let (epoch, rest) = match version.find(':') {
+        Some(pos) => match version[..pos].parse::<u64>() {
+            Ok(epoch) => (epoch, &version[pos + 1..]),
+            Err(_)    => (0, version),
+        },
+        None => (0, version),
+    };
+    match rest.rfind('-') {
+        Some(pos) => (epoch, &rest[..pos], &rest[pos + 1..]),
+        None      => (epoch, rest, ""),
+    }
+}
+
+fn debian_char_order(c: Option<&u8>) -> i32 {
+    match c {
+        None                                => 0,
+        Some(&b'~')                         => -1,
+        Some(c) if c.is_ascii_digit()       => 0,
+        Some(c) if c.is_ascii_alphabetic()  => *c as i32,
+        Some(c)                             => *c as i32 + 256,
+    }
+}
+
+fn debian_verrevcmp(a: &[u8], b: &[u8]) -> Ordering {
+    let is_digit = |s: &[u8], i: usize| {
+        s.get(i).map_or(false, u8::is_ascii_digit)
+    };
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        while (i < a.len() && !is_digit(a, i)) ||
+              (j < b.len() && !is_digit(b, j)) {
+            let a_order = debian_char_order(a.get(i));
+            let b_order = debian_char_order(b.get(j));
+            if a_order != b_order {
+                return a_order.cmp(&b_order);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        while a.get(i) == Some(&b'0') { i += 1; }
+        while b.get(j) == Some(&b'0') { j += 1; }
+
+        let mut first_diff = Ordering::Equal;
+        while is_digit(a, i) && is_digit(b, j) {
+            if first_diff == Ordering::Equal {
+                first_diff = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+        if is_digit(a, i) {
+            return Ordering::Greater;
+        }
+        if is_digit(b, j) {
+            return Ordering::Less;
+        }
+        if first_diff != Ordering::Equal {
+            return first_diff;
+        }
+    }
+
+    Ordering::Equal
+}
+
+/// Compares two versions of the form `[epoch:]upstream[-revision]` like
+/// `dpkg --compare-versions` does: `3.8.0~rc.1 < 3.8.0 < 3.8.0-1`.
+pub fn compare_debian(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a_upstream, a_revision) = split_debian(a);
+    let (b_epoch, b_upstream, b_revision) = split_debian(b);
+
+    a_epoch.cmp(&b_epoch)
+        .then_with(|| debian_verrevcmp(a_upstream.as_bytes(),
+                                       b_upstream.as_bytes()))
+        .then_with(|| debian_verrevcmp(a_revision.as_bytes(),
+                                       b_revision.as_bytes()))
+}
+
+// -------------------------------------------------------------------
+// RPM versions (same algorithm as rpmvercmp).
+// -------------------------------------------------------------------
+
+fn split_rpm(version: &str) -> (u64, &str, Option<&str>) {
+    let (epoch, rest) = match version.find(':') {
+        Some(pos) => match version[..pos].parse::<u64>() {
+            Ok(epoch) => (epoch, &version[pos + 1..]),
+            Err(_)    => (0, version),
+        },
+        None => (0, version),
+    };
+    match rest.rfind('-') {
+        Some(pos) => (epoch, &rest[..pos], Some(&rest[pos + 1..])),
+        None      => (epoch, rest, None),
+    }
+}
+
+fn rpmvercmp(a: &[u8], b: &[u8]) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let is_separator = |c: u8| {
+        !c.is_ascii_alphanumeric() && c != b'~' && c != b'^'
+    };
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        while i < a.len() && is_separator(a[i]) { i += 1; }
+        while j < b.len() && is_separator(b[j]) { j += 1; }
+
+        // The tilde separator sorts before everything else.
+        if a.get(i) == Some(&b'~') || b.get(j) == Some(&b'~') {
+            if a.get(i) != Some(&b'~') { return Ordering::Greater; }
+            if b.get(j) != Some(&b'~') { return Ordering::Less; }
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        // The caret separator sorts after the end of the string but
+        // before everything else.
+        if a.get(i) == Some(&b'^') || b.get(j) == Some(&b'^') {
+            if i == a.len() { return Ordering::Less; }
+            if j == b.len() { return Ordering::Greater; }
+            if a[i] != b'^' { return Ordering::Greater; }
+            if b[j] != b'^' { return Ordering::Less; }
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        if i == a.len() || j == b.len() {
+            break;
+        }
+
+        let (a_start, b_start) = (i, j);
+        let is_num = a[i].is_ascii_digit();
+        if is_num {
+            while i < a.len() && a[i].is_ascii_digit() { i += 1; }
+            while j < b.len() && b[j].is_ascii_digit() { j += 1; }
+        } else {
+            while i < a.len() && a[i].is_ascii_alphabetic() { i += 1; }
+            while j < b.len() && b[j].is_ascii_alphabetic() { j += 1; }
+        }
+
+        // Numeric segments are always newer than alpha segments.
+        if b_start == j {
+            return if is_num { Ordering::Greater } else { Ordering::Less };
+        }
+
+        let mut a_seg = &a[a_start..i];
+        let mut b_seg = &b[b_start..j];
+        if is_num {
+            while a_seg.first() == Some(&b'0') { a_seg = &a_seg[1..]; }
+            while b_seg.first() == Some(&b'0') { b_seg = &b_seg[1..]; }
+            match a_seg.len().cmp(&b_seg.len()) {
+                Ordering::Equal => { }
+                ordering        => return ordering,
+            }
+        }
+        match a_seg.cmp(b_seg) {
+            Ordering::Equal => { }
+            ordering        => return ordering,
+        }
+    }
+
+    match (i == a.len(), j == b.len()) {
+        (true, true)  => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, _)    => Ordering::Greater,
+    }
+}
+
+/// Compares two versions of the form `[epoch:]version[-release]` like
+/// `rpmdev-vercmp` does. Releases are only compared when both versions
+/// have one.
+pub fn compare_rpm(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a_version, a_release) = split_rpm(a);
+    let (b_epoch, b_version, b_release) = split_rpm(b);
+
+    a_epoch.cmp(&b_epoch)
+        .then_with(|| rpmvercmp(a_version.as_bytes(), b_version.as_bytes()))
+        .then_with(|| match (a_release, b_release) {
+            (Some(a), Some(b)) => rpmvercmp(a.as_bytes(), b.as_bytes()),
+            _                  => Ordering::Equal,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering;
+
+    /// Asserts that `versions` are sorted from the oldest to the most
+    /// recent, and that each one equals itself.
+    fn assert_sorted(order: VersionOrder, versions: &[&str]) {
+        for (i, a) in versions.iter().enumerate() {
+            assert_eq!(order.compare(a, a), Ordering::Equal, "{} = {}", a, a);
+            for b in versions[i + 1..].iter() {
+                assert_eq!(order.compare(a, b), Ordering::Less,
+                           "{} < {}", a, b);
+                assert_eq!(order.compare(b, a), Ordering::Greater,
+                           "{} > {}", b, a);
+            }
+        }
+    }
+
+    #[test]
+    fn semver_pre_releases() {
+        assert_sorted(VersionOrder::Semver,
+                      &["3.8.0-beta.2", "3.8.0-rc.1", "3.8.0-rc.2", "3.8.0"]);
+        assert_sorted(VersionOrder::Semver,
+                      &["3.8.0-beta", "3.8.0-beta.2", "3.8.0-beta.11",
+                        "3.8.0-rc"]);
+    }
+
+    #[test]
+    fn semver_releases() {
+        assert_sorted(VersionOrder::Semver,
+                      &["3.7.9", "3.7.20", "3.8.0", "3.8.1", "3.10.0"]);
+        assert_eq!(compare_semver("3.8", "3.8.0"), Ordering::Equal);
+        assert_eq!(compare_semver("v3.8.0", "3.8.0+build.1"),
+                   Ordering::Equal);
+    }
+
+    #[test]
+    fn semver_invalid_versions_sort_first() {
+        assert_sorted(VersionOrder::Semver, &["latest", "nightly", "0.0.1"]);
+    }
+
+    #[test]
+    fn debian_tilde() {
+        assert_sorted(VersionOrder::Debian,
+                      &["3.8.0~beta.2", "3.8.0~rc.1", "3.8.0~rc.2", "3.8.0",
+                        "3.8.0-1", "3.8.0-2", "3.8.0-10"]);
+        assert_sorted(VersionOrder::Debian, &["1.0~~", "1.0~", "1.0", "1.0a"]);
+    }
+
+    #[test]
+    fn debian_epoch() {
+        assert_sorted(VersionOrder::Debian, &["3.8.0", "1:3.7.0", "2:0.1"]);
+        assert_eq!(compare_debian("0:3.8.0", "3.8.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn debian_releases() {
+        assert_sorted(VersionOrder::Debian,
+                      &["3.7.20", "3.8.0", "3.8.0+dfsg", "3.8.1", "3.10.0"]);
+    }
+
+    #[test]
+    fn rpm_alpha_and_numeric_segments() {
+        assert_sorted(VersionOrder::Rpm, &["1.0", "1.0a", "1.0.1"]);
+        assert_sorted(VersionOrder::Rpm,
+                      &["1.0~rc1", "1.0", "1.0^git1", "1.0.1"]);
+        assert_eq!(compare_rpm("1.0010", "1.10"), Ordering::Equal);
+        assert_eq!(compare_rpm("1.0_1", "1.0.1"), Ordering::Equal);
+    }
+
+    #[test]
+    fn rpm_epoch_and_release() {
+        assert_sorted(VersionOrder::Rpm,
+                      &["3.8.0-1.el8", "3.8.0-2.el8", "1:3.7.0-1.el8"]);
+        // Releases are only compared when both versions have one.
+        assert_eq!(compare_rpm("3.8.0", "3.8.0-1.el8"), Ordering::Equal);
+    }
+
+    #[test]
+    fn chronological_keeps_order() {
+        assert_eq!(VersionOrder::Chronological.compare("3.8.0", "3.7.0"),
+                   Ordering::Equal);
+    }
+}
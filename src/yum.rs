@@ -0,0 +1,389 @@
+//! Generation of the `repodata` of a YUM/DNF repository:
+//!
+//! ```text
+//! <dir>/repodata/repomd.xml
+//! <dir>/repodata/primary.xml.gz
+//! <dir>/repodata/filelists.xml.gz
+//! <dir>/repodata/other.xml.gz
+//! ```
+//!
+//! The documents follow the format written by `createrepo`.
+
+use checksum;
+use chrono::Utc;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rpm::{self, Dependency, Header};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const RPMSENSE_LESS: u64 = 1 << 1;
+const RPMSENSE_GREATER: u64 = 1 << 2;
+const RPMSENSE_EQUAL: u64 = 1 << 3;
+const RPMSENSE_PREREQ: u64 = 1 << 6;
+const RPMSENSE_SCRIPT_PRE: u64 = 1 << 9;
+const RPMSENSE_SCRIPT_POST: u64 = 1 << 10;
+
+/// A package to list in the repodata.
+pub struct YumPackage {
+    /// Path of the package in the repository.
+    pub path: PathBuf,
+    /// Path of the local copy of the package.
+    pub filename: PathBuf,
+    pub header: Header,
+}
+
+/// Writes the `repodata` listing `packages` below `dir` and returns the
+/// path of the written files, relative to `dir`.
+///
+/// The `repodata` directories are at `depth` in the repository: each one
+/// lists the packages below its parent directory. Packages less deep are
+/// not listed.
+pub fn write_repodata(packages: &[YumPackage], depth: usize, dir: &Path)
+    -> io::Result<Vec<PathBuf>>
+{
+    let mut repositories: Vec<(PathBuf, Vec<&YumPackage>)> = vec![];
+    for package in packages {
+        let parents = package.path.parent()
+            .map_or(vec![], |p| p.iter().collect::<Vec<_>>());
+        if parents.len() < depth {
+            continue;
+        }
+        let repository = parents[..depth].iter().collect::<PathBuf>();
+        match repositories.iter().position(|&(ref r, _)| *r == repository) {
+            Some(i) => repositories[i].1.push(package),
+            None    => repositories.push((repository, vec![package])),
+        }
+    }
+
+    let mut written = vec![];
+    for &(ref repository, ref packages) in repositories.iter() {
+        let repodata = repository.join("repodata");
+        fs::create_dir_all(dir.join(&repodata))?;
+
+        let mut entries = vec![];
+        for package in packages {
+            let href = package.path.strip_prefix(repository).unwrap();
+            entries.push(PackageEntry::new(package, href)?);
+        }
+        entries.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+
+        let documents = [("primary", primary_xml(&entries)),
+                         ("filelists", filelists_xml(&entries)),
+                         ("other", other_xml(&entries))];
+        let mut data = String::new();
+        for &(name, ref document) in documents.iter() {
+            let filename = format!("{}.xml.gz", name);
+            let path = dir.join(&repodata).join(&filename);
+            let mut encoder = GzEncoder::new(File::create(&path)?,
+                                             Compression::Default);
+            encoder.write_all(document.as_bytes())?;
+            encoder.finish()?;
+
+            data.push_str(&format!(
+                    "<data type=\"{}\">\n  \
+                     <checksum type=\"sha256\">{}</checksum>\n  \
+                     <open-checksum type=\"sha256\">{}</open-checksum>\n  \
+                     <location href=\"repodata/{}\"/>\n  \
+                     <timestamp>{}</timestamp>\n  \
+                     <size>{}</size>\n  \
+                     <open-size>{}</open-size>\n\
+                     </data>\n",
+                    name,
+                    checksum::sha256_file(&path)?,
+                    checksum::to_hex(&checksum::sha256(document.as_bytes())),
+                    filename,
+                    Utc::now().timestamp(),
+                    fs::metadata(&path)?.len(),
+                    document.len()));
+            written.push(repodata.join(filename));
+        }
+
+        let repomd = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <repomd xmlns=\"http://linux.duke.edu/metadata/repo\" \
+             xmlns:rpm=\"http://linux.duke.edu/metadata/rpm\">\n\
+             <revision>{}</revision>\n\
+             {}\
+             </repomd>\n",
+            Utc::now().timestamp(),
+            data);
+        File::create(dir.join(&repodata).join("repomd.xml"))?
+            .write_all(repomd.as_bytes())?;
+        written.push(repodata.join("repomd.xml"));
+    }
+    Ok(written)
+}
+
+/// What the documents list about a package.
+struct PackageEntry<'a> {
+    header: &'a Header,
+    href: String,
+    sha256: String,
+    size: u64,
+    mtime: u64,
+}
+
+impl<'a> PackageEntry<'a> {
+    fn new(package: &'a YumPackage, href: &Path)
+        -> io::Result<PackageEntry<'a>>
+    {
+        let metadata = fs::metadata(&package.filename)?;
+        let mtime = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(PackageEntry {
+            header: &package.header,
+            href: href.to_string_lossy().into_owned(),
+            sha256: checksum::sha256_file(&package.filename)?,
+            size: metadata.len(),
+            mtime: mtime,
+        })
+    }
+
+    fn sort_key(&self) -> (String, String, String, String) {
+        (self.header.name(),
+         self.header.arch(),
+         self.header.version(),
+         self.header.release())
+    }
+
+    /// The `pkgid`, `name` and `arch` attributes of `filelists.xml` and
+    /// `other.xml`, followed by the version element.
+    fn package_element(&self) -> String {
+        format!("<package pkgid=\"{}\" name=\"{}\" arch=\"{}\">\n  \
+                 <version epoch=\"{}\" ver=\"{}\" rel=\"{}\"/>\n",
+                self.sha256,
+                escape(&self.header.name()),
+                escape(&self.header.arch()),
+                self.header.epoch(),
+                escape(&self.header.version()),
+                escape(&self.header.release()))
+    }
+
+    fn files(&self) -> Vec<(String, Option<&'static str>)> {
+        let modes = self.header.integers(rpm::FILEMODES);
+        let flags = self.header.integers(rpm::FILEFLAGS);
+        self.header.files()
+            .into_iter()
+            .enumerate()
+            .map(|(i, file)| {
+                let type_ = if modes.get(i).map_or(false,
+                                                   |m| m & 0o170000 == 0o40000) {
+                    Some("dir")
+                } else if flags.get(i).map_or(false,
+                                              |f| f & rpm::FILE_GHOST != 0) {
+                    Some("ghost")
+                } else {
+                    None
+                };
+                (file, type_)
+            })
+            .collect()
+    }
+}
+
+fn primary_xml(entries: &[PackageEntry]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <metadata xmlns=\"http://linux.duke.edu/metadata/common\" \
+         xmlns:rpm=\"http://linux.duke.edu/metadata/rpm\" packages=\"{}\">\n",
+        entries.len());
+    for entry in entries {
+        let header = entry.header;
+        let text = |tag| escape(&header.string(tag).unwrap_or_default());
+        xml.push_str(&format!(
+                "<package type=\"rpm\">\n  \
+                 <name>{}</name>\n  \
+                 <arch>{}</arch>\n  \
+                 <version epoch=\"{}\" ver=\"{}\" rel=\"{}\"/>\n  \
+                 <checksum type=\"sha256\" pkgid=\"YES\">{}</checksum>\n  \
+                 <summary>{}</summary>\n  \
+                 <description>{}</description>\n  \
+                 <packager>{}</packager>\n  \
+                 <url>{}</url>\n  \
+                 <time file=\"{}\" build=\"{}\"/>\n  \
+                 <size package=\"{}\" installed=\"{}\" archive=\"{}\"/>\n  \
+                 <location href=\"{}\"/>\n  \
+                 <format>\n    \
+                 <rpm:license>{}</rpm:license>\n    \
+                 <rpm:vendor>{}</rpm:vendor>\n    \
+                 <rpm:group>{}</rpm:group>\n    \
+                 <rpm:buildhost>{}</rpm:buildhost>\n    \
+                 <rpm:sourcerpm>{}</rpm:sourcerpm>\n    \
+                 <rpm:header-range start=\"{}\" end=\"{}\"/>\n",
+                escape(&header.name()),
+                escape(&header.arch()),
+                header.epoch(),
+                escape(&header.version()),
+                escape(&header.release()),
+                entry.sha256,
+                text(rpm::SUMMARY),
+                text(rpm::DESCRIPTION),
+                text(rpm::PACKAGER),
+                text(rpm::URL),
+                entry.mtime,
+                header.integer(rpm::BUILDTIME).unwrap_or(0),
+                entry.size,
+                header.integer(rpm::SIZE)
+                    .or(header.integer(rpm::LONGSIZE))
+                    .unwrap_or(0),
+                header.integer(rpm::ARCHIVESIZE).unwrap_or(0),
+                escape(&entry.href),
+                text(rpm::LICENSE),
+                text(rpm::VENDOR),
+                text(rpm::GROUP),
+                text(rpm::BUILDHOST),
+                text(rpm::SOURCERPM),
+                header.start,
+                header.end));
+
+        let requires = header.dependencies(rpm::REQUIRENAME,
+                                           rpm::REQUIREFLAGS,
+                                           rpm::REQUIREVERSION)
+            .into_iter()
+            .filter(|d| !d.name.starts_with("rpmlib("))
+            .collect::<Vec<Dependency>>();
+        let dependencies = [
+            ("provides", header.dependencies(rpm::PROVIDENAME,
+                                             rpm::PROVIDEFLAGS,
+                                             rpm::PROVIDEVERSION)),
+            ("requires", requires),
+            ("conflicts", header.dependencies(rpm::CONFLICTNAME,
+                                              rpm::CONFLICTFLAGS,
+                                              rpm::CONFLICTVERSION)),
+            ("obsoletes", header.dependencies(rpm::OBSOLETENAME,
+                                              rpm::OBSOLETEFLAGS,
+                                              rpm::OBSOLETEVERSION)),
+        ];
+        for &(kind, ref dependencies) in dependencies.iter() {
+            if dependencies.is_empty() {
+                continue;
+            }
+            xml.push_str(&format!("    <rpm:{}>\n", kind));
+            for dependency in dependencies {
+                xml.push_str(&format!("      {}\n",
+                                      dependency_entry(dependency,
+                                                       kind == "requires")));
+            }
+            xml.push_str(&format!("    </rpm:{}>\n", kind));
+        }
+
+        // Like createrepo, only list the files likely to be required by
+        // other packages.
+        for (file, type_) in entry.files() {
+            if file.starts_with("/etc/") || file.contains("bin/") ||
+                file == "/usr/lib/sendmail" {
+                xml.push_str(&file_element("    ", &file, type_));
+            }
+        }
+        xml.push_str("  </format>\n</package>\n");
+    }
+    xml.push_str("</metadata>\n");
+    xml
+}
+
+fn filelists_xml(entries: &[PackageEntry]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <filelists xmlns=\"http://linux.duke.edu/metadata/filelists\" \
+         packages=\"{}\">\n",
+        entries.len());
+    for entry in entries {
+        xml.push_str(&entry.package_element());
+        for (file, type_) in entry.files() {
+            xml.push_str(&file_element("  ", &file, type_));
+        }
+        xml.push_str("</package>\n");
+    }
+    xml.push_str("</filelists>\n");
+    xml
+}
+
+fn other_xml(entries: &[PackageEntry]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <otherdata xmlns=\"http://linux.duke.edu/metadata/other\" \
+         packages=\"{}\">\n",
+        entries.len());
+    for entry in entries {
+        xml.push_str(&entry.package_element());
+        let header = entry.header;
+        let times = header.integers(rpm::CHANGELOGTIME);
+        let names = header.strings(rpm::CHANGELOGNAME);
+        let texts = header.strings(rpm::CHANGELOGTEXT);
+        for ((time, name), text) in times.iter().zip(names).zip(texts) {
+            xml.push_str(&format!(
+                    "  <changelog author=\"{}\" date=\"{}\">{}</changelog>\n",
+                    escape(&name), time, escape(&text)));
+        }
+        xml.push_str("</package>\n");
+    }
+    xml.push_str("</otherdata>\n");
+    xml
+}
+
+fn dependency_entry(dependency: &Dependency, with_pre: bool) -> String {
+    let mut entry = format!("<rpm:entry name=\"{}\"", escape(&dependency.name));
+
+    let flags = match dependency.flags &
+        (RPMSENSE_LESS | RPMSENSE_GREATER | RPMSENSE_EQUAL) {
+        f if f == RPMSENSE_LESS                     => Some("LT"),
+        f if f == RPMSENSE_GREATER                  => Some("GT"),
+        f if f == RPMSENSE_EQUAL                    => Some("EQ"),
+        f if f == RPMSENSE_LESS | RPMSENSE_EQUAL    => Some("LE"),
+        f if f == RPMSENSE_GREATER | RPMSENSE_EQUAL => Some("GE"),
+        _                                           => None,
+    };
+    match flags {
+        Some(flags) if !dependency.version.is_empty() => {
+            let (epoch, version, release) = split_evr(&dependency.version);
+            entry.push_str(&format!(" flags=\"{}\" epoch=\"{}\" ver=\"{}\"",
+                                    flags, epoch, escape(version)));
+            match release {
+                Some(release) => {
+                    entry.push_str(&format!(" rel=\"{}\"", escape(release)));
+                }
+                None => { }
+            }
+        }
+        _ => { }
+    }
+
+    let pre = RPMSENSE_PREREQ | RPMSENSE_SCRIPT_PRE | RPMSENSE_SCRIPT_POST;
+    if with_pre && dependency.flags & pre != 0 {
+        entry.push_str(" pre=\"1\"");
+    }
+    entry.push_str("/>");
+    entry
+}
+
+/// Splits `[epoch:]version[-release]`. The epoch defaults to 0.
+fn split_evr(evr: &str) -> (&str, &str, Option<&str>) {
+    let (epoch, rest) = match evr.find(':') {
+        Some(i) => (&evr[..i], &evr[i + 1..]),
+        None    => ("0", evr),
+    };
+    match rest.rfind('-') {
+        Some(i) => (epoch, &rest[..i], Some(&rest[i + 1..])),
+        None    => (epoch, rest, None),
+    }
+}
+
+fn file_element(indent: &str, file: &str, type_: Option<&str>) -> String {
+    match type_ {
+        Some(type_) => format!("{}<file type=\"{}\">{}</file>\n",
+                               indent, type_, escape(file)),
+        None => format!("{}<file>{}</file>\n", indent, escape(file)),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}